@@ -0,0 +1,107 @@
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+
+use crate::config::AppConfig;
+
+/// 管理接口通用响应结构
+#[derive(Serialize)]
+pub struct AdminResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// 常量时间比较，避免通过响应时间推测令牌内容
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 从请求中提取管理令牌
+/// 支持 `Authorization: Bearer <token>` 和 `X-Admin-Token: <token>`
+fn extract_token(req: &HttpRequest) -> Option<&str> {
+    if let Some(auth) = req.headers().get("Authorization") {
+        if let Some(token) = auth.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")) {
+            return Some(token.trim());
+        }
+    }
+
+    req.headers()
+        .get("X-Admin-Token")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim())
+}
+
+/// 检查请求是否携带了正确的管理令牌
+pub fn is_authorized(req: &HttpRequest, config: &AppConfig) -> bool {
+    let Some(expected) = config.admin_token.as_deref() else {
+        log::warn!("未配置 ADMIN_TOKEN，拒绝管理请求");
+        return false;
+    };
+
+    match extract_token(req) {
+        Some(token) => constant_time_eq(token.as_bytes(), expected.as_bytes()),
+        None => false,
+    }
+}
+
+/// 令牌校验失败时的响应
+pub fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized().json(AdminResponse {
+        success: false,
+        message: Some("管理令牌无效".to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn config_with_token(token: Option<&str>) -> AppConfig {
+        let mut config = AppConfig::for_tests();
+        config.admin_token = token.map(|t| t.to_string());
+        config
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret!"));
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let config = config_with_token(Some("secret"));
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_http_request();
+        assert!(is_authorized(&req, &config));
+
+        let req = TestRequest::default()
+            .insert_header(("X-Admin-Token", "secret"))
+            .to_http_request();
+        assert!(is_authorized(&req, &config));
+
+        let req = TestRequest::default()
+            .insert_header(("X-Admin-Token", "wrong"))
+            .to_http_request();
+        assert!(!is_authorized(&req, &config));
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!is_authorized(&req, &config));
+    }
+
+    #[test]
+    fn test_no_token_configured_rejects() {
+        let config = config_with_token(None);
+        let req = TestRequest::default()
+            .insert_header(("X-Admin-Token", ""))
+            .to_http_request();
+        assert!(!is_authorized(&req, &config));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::template;
+
+/// 估算文本宽度（像素）：ASCII 字符按 7 像素，其他字符（如中文）按 12 像素
+fn text_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 7 } else { 12 }).sum()
+}
+
+/// 渲染左侧为标签、右侧为数值的 SVG 徽章
+pub fn render(label: &str, value: &str, color: &str) -> String {
+    let label_width = text_width(label) + 10;
+    let value_width = text_width(value) + 10;
+    let numbers = [
+        label_width + value_width,
+        label_width,
+        value_width,
+        label_width / 2,
+        label_width + value_width / 2,
+    ]
+    .map(|n| n.to_string());
+
+    template::render(include_str!("badge.svg"), &[
+        ("width", &numbers[0]),
+        ("label_width", &numbers[1]),
+        ("value_width", &numbers[2]),
+        ("label_x", &numbers[3]),
+        ("value_x", &numbers[4]),
+        ("label", label),
+        ("value", value),
+        ("color", color),
+    ])
+}
+
+/// 根据徽章内容计算 ETag，内容不变时保持不变
+pub fn etag(label: &str, value: &str, color: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    (label, value, color).hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// 检查 `If-None-Match` 请求头是否与 ETag 匹配（支持 `*`、多个值和弱校验前缀 `W/`）
+pub fn if_none_match(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_none_match() {
+        let tag = etag("Teacon", "42 周", "#667eea");
+        assert!(if_none_match(&tag, &tag));
+        assert!(if_none_match(&format!("\"other\", W/{}", tag), &tag));
+        assert!(if_none_match("*", &tag));
+        assert!(!if_none_match("\"other\"", &tag));
+        assert_ne!(tag, etag("Teacon", "43 周", "#667eea"));
+    }
+
+    #[test]
+    fn test_render_escapes_label() {
+        let svg = render("<Teacon>", "1 周", "#667eea");
+        assert!(svg.contains("&lt;Teacon&gt;"));
+        assert!(!svg.contains("{{"));
+    }
+}
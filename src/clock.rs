@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+
+/// 时间来源，数据库通过它获取当前时间，测试中可替换为手动控制的时钟
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// 系统时钟
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// 手动控制的时钟（仅用于测试）
+#[cfg(test)]
+pub struct ManualClock(std::sync::Mutex<DateTime<Utc>>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        ManualClock(std::sync::Mutex::new(now))
+    }
+
+    /// 将时钟设置到指定时间
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,129 @@
+use actix_http::encoding::Encoder;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    AcceptEncoding, ContentEncoding, Encoding, Header, HeaderMap, CONTENT_TYPE,
+};
+use actix_web::middleware::Next;
+use actix_web::{mime, web, Error};
+
+use crate::config::AppConfig;
+
+/// 默认的压缩阈值（字节）
+pub const DEFAULT_MIN_BYTES: usize = 1024;
+
+/// 可协商的编码，`identity` 排在最后作为兜底
+static SUPPORTED_ENCODINGS: &[Encoding] = &[
+    Encoding::brotli(),
+    Encoding::gzip(),
+    Encoding::deflate(),
+    Encoding::zstd(),
+    Encoding::identity(),
+];
+
+/// 响应体是否值得压缩：只压缩大小已知且不小于阈值的响应
+/// 流式响应（SSE）大小未知，压缩会缓冲事件，同样不压缩
+fn should_compress(size: BodySize, min_bytes: usize) -> bool {
+    matches!(size, BodySize::Sized(len) if len >= min_bytes as u64)
+}
+
+/// 与 actix 的 `Compress` 一致：图片（SVG 除外）和视频已经压缩过，不再压缩
+fn compressible_type(headers: &HeaderMap) -> bool {
+    let mime = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok());
+    match mime {
+        Some(mime) if mime.type_() == mime::IMAGE => mime.subtype() == mime::SVG,
+        Some(mime) if mime.type_() == mime::VIDEO => false,
+        _ => true,
+    }
+}
+
+/// 带阈值的响应压缩中间件，按 `Accept-Encoding` 协商编码
+/// 小于阈值的响应、流式响应原样发送，不带 `Content-Encoding` 头
+pub async fn compress(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let min_bytes = req
+        .app_data::<web::Data<AppConfig>>()
+        .map(|config| config.compression_min_bytes)
+        .unwrap_or(DEFAULT_MIN_BYTES);
+    let encoding = AcceptEncoding::parse(&req)
+        .ok()
+        .and_then(|accept| accept.negotiate(SUPPORTED_ENCODINGS.iter()));
+
+    let res = next.call(req).await?;
+    Ok(res.map_body(move |head, body| {
+        let encoding = match encoding {
+            Some(Encoding::Known(encoding))
+                if should_compress(body.size(), min_bytes) && compressible_type(&head.headers) =>
+            {
+                encoding
+            }
+            _ => ContentEncoding::Identity,
+        };
+        Encoder::response(encoding, head, body)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderValue, CONTENT_ENCODING};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn test_should_compress() {
+        assert!(should_compress(BodySize::Sized(1024), 1024));
+        assert!(!should_compress(BodySize::Sized(1023), 1024));
+        assert!(!should_compress(BodySize::Stream, 0));
+        assert!(!should_compress(BodySize::None, 0));
+    }
+
+    #[actix_web::test]
+    async fn test_compressible_type() {
+        let with_type = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(value));
+            headers
+        };
+        assert!(compressible_type(&HeaderMap::new()));
+        assert!(compressible_type(&with_type("application/json")));
+        assert!(compressible_type(&with_type("image/svg+xml")));
+        assert!(!compressible_type(&with_type("image/png")));
+        assert!(!compressible_type(&with_type("video/mp4")));
+    }
+
+    #[actix_web::test]
+    async fn test_small_responses_are_not_compressed() {
+        let mut config = AppConfig::for_tests();
+        config.compression_min_bytes = 100;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .wrap(from_fn(compress))
+                .route("/small", web::get().to(|| async { HttpResponse::Ok().body("42") }))
+                .route("/large", web::get().to(|| async { HttpResponse::Ok().body("a".repeat(1000)) })),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/small")
+            .insert_header(("Accept-Encoding", "gzip"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(test::read_body(resp).await, "42");
+
+        let req = test::TestRequest::get()
+            .uri("/large")
+            .insert_header(("Accept-Encoding", "gzip"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert!(test::read_body(resp).await.len() < 1000);
+    }
+}
@@ -0,0 +1,317 @@
+use chrono::{DateTime, FixedOffset, Offset, Utc};
+use std::num::NonZeroU32;
+use std::str::FromStr;
+
+use crate::compression;
+use crate::db::{DEFAULT_MAX_BLOCKING_OPS, DEFAULT_MAX_TX_ATTEMPTS, DEFAULT_OP_TIMEOUT};
+use crate::milestones::Milestones;
+
+/// X-Forwarded-For 中可信代理的层数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustedHops {
+    /// 信任所有代理，取第一个条目
+    All,
+    /// 跳过末尾指定数量的可信代理条目
+    Count(usize),
+}
+
+impl FromStr for TrustedHops {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("all") {
+            Ok(TrustedHops::All)
+        } else {
+            value.parse().map(TrustedHops::Count)
+        }
+    }
+}
+
+/// 应用配置，启动时从环境变量读取
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// 数据库存储路径（`DB_PATH`）
+    pub db_path: String,
+    /// 服务器绑定地址（`BIND_ADDRESS`）
+    pub bind_address: String,
+    /// 启用的增加周数钩子名称列表（`INCREMENT_HOOKS`，逗号分隔，如 `log,webhook`）
+    pub increment_hooks: Vec<String>,
+    /// webhook 钩子的目标地址（`WEBHOOK_URL`）
+    pub webhook_url: Option<String>,
+    /// webhook 请求体中附带客户端 IP（`WEBHOOK_INCLUDE_IP`，默认关闭）
+    pub webhook_include_ip: bool,
+    /// 管理接口令牌（`ADMIN_TOKEN`），未设置时所有管理接口均拒绝访问
+    pub admin_token: Option<String>,
+    /// 活动名称（`EVENT_NAME`）
+    pub event_name: String,
+    /// 页面标题（`SITE_TITLE`，默认为 `<活动名称> 开放倒计时`）
+    pub site_title: String,
+    /// 页面主题色（`ACCENT_COLOR`）
+    pub accent_color: String,
+    /// 增加周数事务冲突时的最大尝试次数（`TX_MAX_ATTEMPTS`，必须大于 0），超过后返回 503
+    pub tx_max_attempts: u32,
+    /// 启动时在接受请求前预热周数缓存（`WARM_CACHE`）
+    pub warm_cache: bool,
+    /// 里程碑列表（`MILESTONES`，逗号分隔，如 `100,500,1000`）
+    pub milestones: Milestones,
+    /// 是否随 IP 记录保存截断后的 User-Agent（`TRACK_USER_AGENT`，默认关闭）
+    pub track_user_agent: bool,
+    /// 全服务器两次按钮增加之间的最小间隔（`BUTTON_MIN_INTERVAL_MS`，毫秒，0 表示不限制）
+    pub button_min_interval_ms: u64,
+    /// X-Forwarded-For 中可信代理的层数（`TRUSTED_HOPS`，数字或 `all`，默认 `all`）
+    pub trusted_hops: TrustedHops,
+    /// SSE 连接空闲时发送心跳注释的间隔（`SSE_HEARTBEAT_SECS`，秒，默认 15）
+    pub sse_heartbeat_secs: u64,
+    /// 周数为 0 时在 `/api/data` 响应中附带 `is_initial: true`（`MARK_INITIAL`，默认关闭）
+    pub mark_initial: bool,
+    /// 周数为 0 时在 `/api/data` 响应中附带的提示消息（`INITIAL_MESSAGE`）
+    pub initial_message: Option<String>,
+    /// JSON 请求体的最大字节数（`MAX_BODY_BYTES`，默认 65536）
+    pub max_body_bytes: usize,
+    /// 幂等键的有效期（`IDEMPOTENCY_TTL_SECS`，秒，默认 86400），过期的键由后台任务定期清理
+    pub idempotency_ttl_secs: u64,
+    /// 徽章响应的 `Cache-Control: max-age`（`BADGE_MAX_AGE_SECS`，秒，默认 60）
+    pub badge_max_age_secs: u64,
+    /// 上一季时间序列文件（`PREVIOUS_SEASON_FILE`），用于 `/api/stats/vs-previous`
+    pub previous_season_file: Option<String>,
+    /// 同时进行的阻塞数据库操作数上限（`DB_MAX_CONCURRENCY`，默认 64），超出的请求排队等待
+    pub db_max_concurrency: usize,
+    /// 单次数据库操作的超时时间（`DB_TIMEOUT_MS`，毫秒，默认 5000），超时后返回 503
+    pub db_timeout_ms: u64,
+    /// 禁用不去重的按钮接口 `/api/increment`（`DISABLE_BUTTON`，默认关闭），禁用后该路径返回 404
+    pub disable_button: bool,
+    /// Pushgateway 地址（`PUSHGATEWAY_URL`），设置后定期推送指标
+    pub pushgateway_url: Option<String>,
+    /// 推送使用的任务名（`PUSHGATEWAY_JOB`，默认 `teacon_counter`）
+    pub pushgateway_job: String,
+    /// 推送间隔（`PUSHGATEWAY_INTERVAL_SECS`，秒，默认 60）
+    pub pushgateway_interval_secs: u64,
+    /// 冻结时间（`FREEZE_AT`，RFC3339），到达后所有增加周数的接口不再计数，读取不受影响
+    pub freeze_at: Option<DateTime<Utc>>,
+    /// 统计展示使用的时区偏移（`DISPLAY_UTC_OFFSET`，如 `+08:00`，默认 UTC）
+    pub display_utc_offset: FixedOffset,
+    /// 新数据库的初始周数（`INITIAL_COUNT`，默认 0），已有周数时忽略
+    pub initial_count: u64,
+    /// MaxMind 国家数据库文件（`GEOIP_DB_PATH`，如 GeoLite2-Country.mmdb），设置后首页访问按国家计数
+    pub geoip_db_path: Option<String>,
+    /// 响应压缩阈值（`COMPRESSION_MIN_BYTES`，字节，默认 1024），更小的响应不压缩
+    pub compression_min_bytes: usize,
+    /// OTLP 收集器地址（`OTEL_EXPORTER_OTLP_ENDPOINT`），设置且编译时启用 `otel` 特性时导出指标
+    pub otel_endpoint: Option<String>,
+    /// 重置后可以撤销的期限（`RESET_UNDO_GRACE_SECS`，秒，默认 300）
+    pub reset_undo_grace_secs: u64,
+    /// 按信任启发式为按钮增加加权（`WEIGHTED_INCREMENTS`，默认关闭）
+    pub weighted_increments: bool,
+}
+
+impl AppConfig {
+    /// 从环境变量读取配置
+    pub fn from_env() -> Self {
+        Self::from_source(&EnvSource::process())
+    }
+
+    /// 全部使用默认值的配置，测试据此只覆盖需要的字段，不受运行环境的环境变量影响
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self::from_source(&EnvSource::empty())
+    }
+
+    fn from_source(env: &EnvSource) -> Self {
+        let event_name = env.or("EVENT_NAME", "Teacon");
+        let site_title = env.opt("SITE_TITLE")
+            .unwrap_or_else(|| format!("{} 开放倒计时", event_name));
+
+        AppConfig {
+            db_path: env.or("DB_PATH", "./data/db"),
+            bind_address: env.or("BIND_ADDRESS", "0.0.0.0:8080"),
+            increment_hooks: env.list("INCREMENT_HOOKS"),
+            webhook_url: env.opt("WEBHOOK_URL"),
+            webhook_include_ip: env.bool("WEBHOOK_INCLUDE_IP", false),
+            admin_token: env.opt("ADMIN_TOKEN"),
+            event_name,
+            site_title,
+            accent_color: env.or("ACCENT_COLOR", "#667eea"),
+            tx_max_attempts: env.parse("TX_MAX_ATTEMPTS", NonZeroU32::new(DEFAULT_MAX_TX_ATTEMPTS).unwrap()).get(),
+            warm_cache: env.bool("WARM_CACHE", false),
+            milestones: env.opt("MILESTONES")
+                .map(|value| Milestones::parse(&value))
+                .unwrap_or_default(),
+            track_user_agent: env.bool("TRACK_USER_AGENT", false),
+            button_min_interval_ms: env.parse("BUTTON_MIN_INTERVAL_MS", 0),
+            trusted_hops: env.parse("TRUSTED_HOPS", TrustedHops::All),
+            sse_heartbeat_secs: env.parse("SSE_HEARTBEAT_SECS", 15).max(1),
+            mark_initial: env.bool("MARK_INITIAL", false),
+            initial_message: env.opt("INITIAL_MESSAGE"),
+            max_body_bytes: env.parse("MAX_BODY_BYTES", 64 * 1024),
+            idempotency_ttl_secs: env.parse("IDEMPOTENCY_TTL_SECS", 24 * 60 * 60).max(1),
+            badge_max_age_secs: env.parse("BADGE_MAX_AGE_SECS", 60),
+            previous_season_file: env.opt("PREVIOUS_SEASON_FILE"),
+            db_max_concurrency: env.parse("DB_MAX_CONCURRENCY", DEFAULT_MAX_BLOCKING_OPS).max(1),
+            db_timeout_ms: env.parse("DB_TIMEOUT_MS", DEFAULT_OP_TIMEOUT.as_millis() as u64).max(1),
+            disable_button: env.bool("DISABLE_BUTTON", false),
+            pushgateway_url: env.opt("PUSHGATEWAY_URL"),
+            pushgateway_job: env.or("PUSHGATEWAY_JOB", "teacon_counter"),
+            pushgateway_interval_secs: env.parse("PUSHGATEWAY_INTERVAL_SECS", 60).max(1),
+            freeze_at: env.time("FREEZE_AT"),
+            display_utc_offset: env.parse("DISPLAY_UTC_OFFSET", Utc.fix()),
+            initial_count: env.parse("INITIAL_COUNT", 0),
+            geoip_db_path: env.opt("GEOIP_DB_PATH"),
+            compression_min_bytes: env.parse("COMPRESSION_MIN_BYTES", compression::DEFAULT_MIN_BYTES),
+            otel_endpoint: env.opt("OTEL_EXPORTER_OTLP_ENDPOINT"),
+            reset_undo_grace_secs: env.parse("RESET_UNDO_GRACE_SECS", 5 * 60),
+            weighted_increments: env.bool("WEIGHTED_INCREMENTS", false),
+        }
+    }
+}
+
+/// 配置来源，默认读取进程的环境变量
+struct EnvSource {
+    lookup: fn(&str) -> Option<String>,
+}
+
+impl EnvSource {
+    fn process() -> Self {
+        EnvSource {
+            lookup: |name| std::env::var(name).ok(),
+        }
+    }
+
+    /// 没有任何变量的来源，所有配置取默认值
+    #[cfg(test)]
+    fn empty() -> Self {
+        EnvSource { lookup: |_| None }
+    }
+
+    /// 读取环境变量，不存在时使用默认值
+    fn or(&self, name: &str, default: &str) -> String {
+        (self.lookup)(name).unwrap_or_else(|| default.to_string())
+    }
+
+    /// 读取并解析环境变量，不存在或解析失败时使用默认值
+    fn parse<T: FromStr>(&self, name: &str, default: T) -> T {
+        parse_or(name, self.opt(name).as_deref(), default)
+    }
+
+    /// 读取布尔型环境变量（`1`/`true`/`yes`/`on` 为真）
+    fn bool(&self, name: &str, default: bool) -> bool {
+        self.opt(name).map(|value| parse_bool(&value)).unwrap_or(default)
+    }
+
+    /// 读取可选的环境变量，空字符串视为未设置
+    fn opt(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// 读取 RFC3339 格式的时间环境变量，解析失败时视为未设置
+    fn time(&self, name: &str) -> Option<DateTime<Utc>> {
+        let value = self.opt(name)?;
+        match DateTime::parse_from_rfc3339(&value) {
+            Ok(time) => Some(time.with_timezone(&Utc)),
+            Err(e) => {
+                log::warn!("环境变量 {} 的值 {} 不是有效的 RFC3339 时间，忽略: {}", name, value, e);
+                None
+            }
+        }
+    }
+
+    /// 读取逗号分隔的环境变量列表
+    fn list(&self, name: &str) -> Vec<String> {
+        self.opt(name).map(|value| parse_list(&value)).unwrap_or_default()
+    }
+}
+
+/// 解析环境变量 `name` 的值，未设置或解析失败时使用默认值，解析失败时记录警告
+fn parse_or<T: FromStr>(name: &str, value: Option<&str>, default: T) -> T {
+    match value {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            log::warn!("环境变量 {} 的值 {} 无效，使用默认值", name, value);
+            default
+        }),
+        None => default,
+    }
+}
+
+/// 解析布尔值
+fn parse_bool(value: &str) -> bool {
+    matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+/// 解析逗号分隔的列表，忽略空项
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(parse_list("log, webhook,,"), vec!["log", "webhook"]);
+        assert!(parse_list(" , ").is_empty());
+    }
+
+    #[test]
+    fn test_parse_trusted_hops() {
+        assert_eq!("all".parse(), Ok(TrustedHops::All));
+        assert_eq!("ALL".parse(), Ok(TrustedHops::All));
+        assert_eq!("2".parse(), Ok(TrustedHops::Count(2)));
+        assert!("-1".parse::<TrustedHops>().is_err());
+    }
+
+    #[test]
+    fn test_parse_tx_max_attempts_rejects_zero() {
+        // 0 次尝试会使所有写入失败，回退到默认值
+        let default = NonZeroU32::new(DEFAULT_MAX_TX_ATTEMPTS).unwrap();
+        assert_eq!(parse_or("TX_MAX_ATTEMPTS", Some("0"), default).get(), DEFAULT_MAX_TX_ATTEMPTS);
+        assert_eq!(parse_or("TX_MAX_ATTEMPTS", Some("3"), default).get(), 3);
+        assert_eq!(parse_or("TX_MAX_ATTEMPTS", None, default).get(), DEFAULT_MAX_TX_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_config_from_source() {
+        let env = EnvSource {
+            lookup: |name| match name {
+                "TRUSTED_HOPS" => Some("2".to_string()),
+                "SSE_HEARTBEAT_SECS" => Some("0".to_string()),
+                "ADMIN_TOKEN" => Some("  ".to_string()),
+                _ => None,
+            },
+        };
+        let config = AppConfig::from_source(&env);
+        assert_eq!(config.trusted_hops, TrustedHops::Count(2));
+        assert_eq!(config.sse_heartbeat_secs, 1);
+        assert_eq!(config.admin_token, None);
+
+        // 测试配置不读取运行环境
+        let config = AppConfig::for_tests();
+        assert_eq!(config.trusted_hops, TrustedHops::All);
+        assert!(!config.disable_button);
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        assert!(parse_bool("true"));
+        assert!(parse_bool("ON"));
+        assert!(parse_bool("1"));
+        assert!(!parse_bool("false"));
+        assert!(!parse_bool("nope"));
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        let offset = parse_or("DISPLAY_UTC_OFFSET", Some("+08:00"), Utc.fix());
+        assert_eq!(offset.local_minus_utc(), 8 * 3600);
+        // 无效的偏移回退到 UTC
+        let offset = parse_or("DISPLAY_UTC_OFFSET", Some("Asia/Shanghai"), Utc.fix());
+        assert_eq!(offset.local_minus_utc(), 0);
+        assert_eq!(parse_or("DISPLAY_UTC_OFFSET", None, Utc.fix()), Utc.fix());
+    }
+}
@@ -1,10 +1,23 @@
-use sled::{Db, Tree};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree, UnabortableTransactionError,
+};
+use sled::{Db, Transactional, Tree};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use chrono::{DateTime, Utc, Duration};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Timelike, Utc};
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 use thiserror::Error;
 
+use crate::clock::{Clock, SystemClock};
+use crate::geoip::CountryLookup;
+use crate::hooks::HookRegistry;
+
 /// 自定义错误类型，实现 Send
 #[derive(Debug, Error)]
 pub enum DbError {
@@ -20,8 +33,41 @@ pub enum DbError {
     Utf8(#[from] std::str::Utf8Error),
     #[error("Date parse error: {0}")]
     DateParse(#[from] chrono::ParseError),
+    #[error("Database operation timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("Transaction contention: gave up after {0} attempts")]
+    Contention(u32),
+    #[error("Date out of range for {0} weeks")]
+    DateOutOfRange(u64),
+    #[error("Unknown tree: {0}")]
+    UnknownTree(String),
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("Data directory {path} is not writable: {source}")]
+    DataDirNotWritable {
+        path: String,
+        source: std::io::Error,
+    },
 }
 
+/// 周数树中本期增加次数计数器的键，由 `take_period_count` 读取并清零
+const PERIOD_COUNT_KEY: &[u8] = b"period_count";
+
+/// 周数树中累计增加次数计数器的键，只增不减（删除历史记录、重置和导入快照都不会让它变小），用于指标
+const INCREMENTS_TOTAL_KEY: &[u8] = b"increments_total";
+
+/// 未指定来源时使用的来源标签
+pub const DEFAULT_SOURCE: &str = "unknown";
+
+/// 默认的并发阻塞数据库操作数上限
+pub const DEFAULT_MAX_BLOCKING_OPS: usize = 64;
+
+/// 默认的单次数据库操作超时时间
+pub const DEFAULT_OP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// 默认的事务最大尝试次数
+pub const DEFAULT_MAX_TX_ATTEMPTS: u32 = 100;
+
 /// 数据库结构，存储周数和最后访问时间
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WeekData {
@@ -29,77 +75,908 @@ pub struct WeekData {
     pub last_click_time: Option<DateTime<Utc>>,
 }
 
+/// 一次周数增加的结果，传递给增加周数钩子
+/// 除了访问和点击，修正和撤销重置使周数增加时也会通知，此时可能一次增加多周
+#[derive(Debug, Clone, Serialize)]
+pub struct IncrementOutcome {
+    /// 增加前的周数
+    pub previous_count: u64,
+    /// 增加后的周数
+    pub week_count: u64,
+    pub time: DateTime<Utc>,
+    /// 触发增加的客户端 IP（按钮点击时为 None）
+    pub ip: Option<String>,
+}
+
+/// 历史记录条目，每次成功增加周数时写入一条
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub time: DateTime<Utc>,
+    /// 本次增加后的周数
+    pub week_count: u64,
+    /// 触发增加的客户端 IP（按钮点击时为 None）
+    pub ip: Option<String>,
+    /// 增加来源标签（如 `index`、`button`），未指定时为 `unknown`
+    pub source: String,
+    /// 本次增加的权重，以 COUNT_UNIT 为一周
+    pub units: u64,
+    /// 客户端 IP 所属国家（仅在启用 GeoIP 且查询到时记录）
+    pub country: Option<String>,
+}
+
+/// 档案记录条目，记录对周数的管理操作（与记录增加的历史记录分开保存）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub time: DateTime<Utc>,
+    pub action: ArchiveAction,
+}
+
+/// 档案记录的操作类型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ArchiveAction {
+    /// 带原因的周数修正
+    Adjust {
+        /// 请求的修正量
+        delta: i64,
+        /// 实际生效的修正量（不低于 0 时可能小于请求量）
+        applied: i64,
+        previous_count: u64,
+        week_count: u64,
+        reason: String,
+    },
+    /// 重置周数，保存重置前的数据用于撤销
+    Reset { previous: WeekData },
+    /// 撤销重置
+    UndoReset {
+        /// 撤销时的周数（重置后新增的次数）
+        reset_count: u64,
+        week_count: u64,
+    },
+    /// 删除历史记录，`decrement` 时同时将周数减一
+    DeleteHistory {
+        /// 被删除的历史记录键
+        key: [u8; 16],
+        /// 被删除的记录保存的周数
+        entry_count: u64,
+        previous_count: u64,
+        week_count: u64,
+    },
+}
+
+impl ArchiveAction {
+    /// 操作之后的周数
+    fn week_count_after(&self) -> u64 {
+        match self {
+            ArchiveAction::Adjust { week_count, .. }
+            | ArchiveAction::UndoReset { week_count, .. }
+            | ArchiveAction::DeleteHistory { week_count, .. } => *week_count,
+            ArchiveAction::Reset { .. } => 0,
+        }
+    }
+}
+
+/// 一次重置的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResetOutcome {
+    /// 重置前的周数
+    pub previous_count: u64,
+    /// 撤销期限，按数据库时钟计算，与 `undo_reset` 的检查一致
+    pub undo_deadline: DateTime<Utc>,
+}
+
+/// 撤销重置的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UndoReset {
+    /// 已恢复，返回撤销前（重置后新增）和恢复后的周数
+    Restored { reset_count: u64, week_count: u64 },
+    /// 重置已超过撤销期限
+    Expired { reset_at: DateTime<Utc> },
+    /// 没有可撤销的重置
+    NothingToUndo,
+}
+
+/// 幂等键记录，保存首次请求的时间和结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IdempotencyRecord {
+    time: DateTime<Utc>,
+    week_count: u64,
+}
+
+/// 最近一次可撤销的重置对应的档案键，保存在 `weeks` 树中，撤销后删除
+const LAST_RESET_KEY: &[u8] = b"last_reset";
+
+/// 当前快照格式版本
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// 快照中包含的树（使用 sled 中的树名）
+const SNAPSHOT_TREES: [&str; 11] = [
+    "weeks",
+    "clicks",
+    "user_agents",
+    "history",
+    "ip_counts",
+    "daily",
+    "archive",
+    "idempotency",
+    "sources",
+    "countries",
+    "button_clicks",
+];
+
+/// 完整的数据快照，按树名保存所有键值对（键和值均为十六进制）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub trees: BTreeMap<String, Vec<(String, String)>>,
+}
+
+/// 一次周数修正的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Adjustment {
+    pub previous_count: u64,
+    pub week_count: u64,
+    /// 实际生效的修正量
+    pub applied: i64,
+}
+
+/// 某个时间点以来的周数变化
+#[derive(Debug, Clone, Serialize)]
+pub struct WeekDelta {
+    /// 该时间点之后的增加次数（按事件计，不考虑权重）
+    pub increments: u64,
+    /// 该时间点之后增加的周数（按权重计，可能有小数）
+    pub weeks_added: f64,
+    /// 该时间点时的周数（早于所有历史记录和档案记录时为 0）
+    pub count_then: u64,
+    /// 当前周数
+    pub count_now: u64,
+}
+
+/// 阻塞数据库操作的并发状态，用于诊断
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockingStats {
+    /// 同时进行的阻塞数据库操作数上限
+    pub max_blocking_ops: usize,
+    /// 当前空闲的许可数
+    pub available_permits: usize,
+    /// 当前持有许可、正在执行的操作数（不含排队等待的操作）
+    pub in_flight: usize,
+}
+
+/// 单个 IP 的访问记录
+#[derive(Debug, Clone, Serialize)]
+pub struct IpInfo {
+    /// 累计增加次数
+    pub count: u64,
+    pub last_click_time: Option<DateTime<Utc>>,
+    /// 最后一次计数时的 User-Agent（仅在启用跟踪时记录）
+    pub user_agent: Option<String>,
+}
+
+/// 保存的 User-Agent 最大字节数
+pub const MAX_USER_AGENT_LEN: usize = 256;
+
+/// 将 User-Agent 截断到 MAX_USER_AGENT_LEN 字节以内（保持 UTF-8 字符完整）
+fn truncate_user_agent(user_agent: &str) -> &str {
+    if user_agent.len() <= MAX_USER_AGENT_LEN {
+        return user_agent;
+    }
+    let mut end = MAX_USER_AGENT_LEN;
+    while !user_agent.is_char_boundary(end) {
+        end -= 1;
+    }
+    &user_agent[..end]
+}
+
+/// 指标快照，用于 `/metrics` 输出
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    pub week_count: u64,
+    /// 首页访问计数过的不同 IP 数量
+    pub unique_ips: u64,
+    /// 累计增加次数，只增不减
+    pub increments_total: u64,
+    /// 当前 UTC 日的增加次数
+    pub increments_today: u64,
+    /// 最近一次增加的时间
+    pub last_increment_time: Option<DateTime<Utc>>,
+}
+
+/// 生成历史记录的键：时间戳（纳秒，大端序）+ 唯一 ID，保证按时间排序且不重复
+fn history_key(time: DateTime<Utc>, id: u64) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&history_time_prefix(time));
+    key[8..].copy_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// 历史记录键的文本形式（32 位十六进制）
+pub fn format_history_key(key: &[u8]) -> String {
+    encode_hex(key)
+}
+
+/// 将字节编码为小写十六进制字符串
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 解析文本形式的历史记录键，格式不正确时返回 None
+pub fn parse_history_key(key: &str) -> Option<[u8; 16]> {
+    decode_hex(key)?.try_into().ok()
+}
+
+/// 解码十六进制字符串，格式不正确时返回 None
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// 历史记录键的时间前缀，用于按时间范围查询
+fn history_time_prefix(time: DateTime<Utc>) -> [u8; 8] {
+    // 1970 年之前的时间不会出现在历史记录中，统一视为 0
+    let nanos = time.timestamp_nanos_opt().unwrap_or(i64::MAX).max(0) as u64;
+    nanos.to_be_bytes()
+}
+
+/// 解码大端序存储的计数器，长度不符时视为 0
+fn decode_u64(bytes: &[u8]) -> u64 {
+    bytes.try_into().map(u64::from_be_bytes).unwrap_or(0)
+}
+
+/// 每日统计的键（UTC 日期，如 `2024-01-01`），按字典序即按日期排序
+fn daily_key(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+/// 周数的内部精度：一周记为 COUNT_UNIT 个单位，加权增加按单位累计，对外显示四舍五入后的周数
+pub const COUNT_UNIT: u64 = 1000;
+
+/// 以单位保存的精确总数，保存在 `weeks` 树中
+/// 不存在时总数就是 `current_week` 的周数乘以 COUNT_UNIT，只有出现不足一周的变化后才写入
+const WEIGHTED_TOTAL_KEY: &[u8] = b"weighted_total";
+
+/// 将权重换算为单位，权重限制在 0 到 1 之间，NaN 视为 0
+pub fn weight_units(weight: f64) -> u64 {
+    if weight.is_nan() {
+        return 0;
+    }
+    (weight.clamp(0.0, 1.0) * COUNT_UNIT as f64).round() as u64
+}
+
+/// 将以单位计的总数四舍五入（半数进位）为显示的周数
+fn round_units(total: u64) -> u64 {
+    total / COUNT_UNIT + u64::from(total % COUNT_UNIT >= COUNT_UNIT / 2)
+}
+
+/// 在事务中按单位调整精确总数（不低于 0），返回新的显示周数
+/// 总数键不存在且变化量为整周时直接调整周数，不写入总数键，未使用加权的数据保持原有格式
+fn add_units(
+    tree: &TransactionalTree,
+    week_count: u64,
+    delta: i128,
+) -> Result<u64, UnabortableTransactionError> {
+    let clamp = |value: i128| value.clamp(0, u64::MAX as i128) as u64;
+    let total = match tree.get(WEIGHTED_TOTAL_KEY)? {
+        Some(value) => decode_u64(&value) as i128 + delta,
+        None if delta % COUNT_UNIT as i128 == 0 => {
+            return Ok(clamp(week_count as i128 + delta / COUNT_UNIT as i128));
+        }
+        None => week_count as i128 * COUNT_UNIT as i128 + delta,
+    };
+    let total = clamp(total);
+    tree.insert(WEIGHTED_TOTAL_KEY, &total.to_be_bytes())?;
+    Ok(round_units(total))
+}
+
+/// 在事务中将计数器加一，返回新值
+fn bump_counter(tree: &TransactionalTree, key: &[u8]) -> Result<u64, UnabortableTransactionError> {
+    let count = tree.get(key)?.map(|v| decode_u64(&v)).unwrap_or(0) + 1;
+    tree.insert(key, &count.to_be_bytes())?;
+    Ok(count)
+}
+
+/// 在事务中将计数器减一（不低于 0），减到 0 时删除该键
+fn drop_counter(tree: &TransactionalTree, key: &[u8]) -> Result<u64, UnabortableTransactionError> {
+    let count = tree.get(key)?.map(|v| decode_u64(&v)).unwrap_or(0).saturating_sub(1);
+    if count == 0 {
+        tree.remove(key)?;
+    } else {
+        tree.insert(key, &count.to_be_bytes())?;
+    }
+    Ok(count)
+}
+
+/// 检查 IP 在当前时间窗口（同一 UTC 日）内是否已经计数过
+fn ip_counted_in_window(click_tree: &Tree, ip_key: &str, now: DateTime<Utc>) -> Result<bool, DbError> {
+    match click_tree.get(ip_key.as_bytes())? {
+        Some(prev_click_bytes) => click_in_window(&prev_click_bytes, now),
+        None => Ok(false),
+    }
+}
+
+/// 检查记录的访问时间是否与当前时间处于同一时间窗口（同一 UTC 日）
+fn click_in_window(prev_click_bytes: &[u8], now: DateTime<Utc>) -> Result<bool, DbError> {
+    let prev_click_str = std::str::from_utf8(prev_click_bytes)?;
+    let prev_click = DateTime::parse_from_rfc3339(prev_click_str)?;
+    let prev_date = prev_click.date_naive();
+    let current_date = now.date_naive();
+
+    Ok(prev_date == current_date)
+}
+
+/// 检查数据目录可写：不存在时创建，然后写入并删除一个探测文件
+fn check_data_dir(path: &Path) -> Result<(), DbError> {
+    let not_writable = |source| DbError::DataDirNotWritable {
+        path: path.display().to_string(),
+        source,
+    };
+
+    std::fs::create_dir_all(path).map_err(not_writable)?;
+    log_dir_permissions(path);
+
+    let probe = path.join(".write_probe");
+    std::fs::write(&probe, b"probe").map_err(not_writable)?;
+    std::fs::remove_file(&probe).map_err(not_writable)?;
+    Ok(())
+}
+
+/// 记录数据目录的权限模式
+#[cfg(unix)]
+fn log_dir_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    match std::fs::metadata(path) {
+        Ok(metadata) => log::info!(
+            "数据目录 {} 的权限模式: {:o}",
+            path.display(),
+            metadata.permissions().mode() & 0o7777
+        ),
+        Err(e) => log::warn!("读取数据目录 {} 的权限失败: {}", path.display(), e),
+    }
+}
+
+#[cfg(not(unix))]
+fn log_dir_permissions(path: &Path) {
+    match std::fs::metadata(path) {
+        Ok(metadata) => log::info!(
+            "数据目录 {} 只读: {}",
+            path.display(),
+            metadata.permissions().readonly()
+        ),
+        Err(e) => log::warn!("读取数据目录 {} 的权限失败: {}", path.display(), e),
+    }
+}
+
+/// 用一个批次整体替换计数器树的内容：删除多余的键并写入新值
+fn replace_counters(tree: &Tree, counters: BTreeMap<String, u64>) -> Result<(), DbError> {
+    let mut batch = sled::Batch::default();
+    for item in tree.iter().keys() {
+        let key = item?;
+        if !counters.contains_key(std::str::from_utf8(&key)?) {
+            batch.remove(key);
+        }
+    }
+    for (key, count) in counters {
+        batch.insert(key.as_bytes(), &count.to_be_bytes());
+    }
+    tree.apply_batch(batch)?;
+    Ok(())
+}
+
+/// 将事务内的错误转换为中止事务的错误
+fn abort<E: Into<DbError>>(e: E) -> ConflictableTransactionError<DbError> {
+    ConflictableTransactionError::Abort(e.into())
+}
+
+/// 将事务错误转换为 DbError
+fn transaction_error(e: TransactionError<DbError>) -> DbError {
+    match e {
+        // 事务被中止，取出中止原因
+        TransactionError::Abort(err) => err,
+        // 存储错误
+        TransactionError::Storage(err) => DbError::Sled(err),
+    }
+}
+
+/// 一次增加在事务外预先确定的信息，事务重试时保持不变
+struct PendingIncrement {
+    now: DateTime<Utc>,
+    history_id: u64,
+    day: String,
+    source: String,
+    /// 增加的权重，以 COUNT_UNIT 为一周
+    units: u64,
+}
+
+impl PendingIncrement {
+    fn new(db: &Db, now: DateTime<Utc>, source: Option<&str>, units: u64) -> Result<Self, DbError> {
+        Ok(PendingIncrement {
+            now,
+            history_id: db.generate_id()?,
+            day: daily_key(now.date_naive()),
+            source: source.unwrap_or(DEFAULT_SOURCE).to_string(),
+            units,
+        })
+    }
+}
+
+/// 在事务中直接增加周数（不检查 IP），写入历史记录并累加每日、本期和来源计数
+/// 周数按权重累加；每日、本期和来源计数按事件累加，每次增加计 1
+/// 返回新的显示周数，以及显示周数是否变化（不足一周的加权增加可能不改变显示值）
+fn apply_increment(
+    tree: &TransactionalTree,
+    history: &TransactionalTree,
+    daily: &TransactionalTree,
+    sources: &TransactionalTree,
+    pending: &PendingIncrement,
+) -> ConflictableTransactionResult<(u64, u64), DbError> {
+    let key = b"current_week";
+
+    // 获取当前数据
+    let mut data: WeekData = if let Some(value) = tree.get(key)? {
+        // 手动反序列化以处理事务中的错误
+        bincode::deserialize(&value).map_err(abort)?
+    } else {
+        WeekData {
+            week_count: 0,
+            last_click_time: None,
+        }
+    };
+
+    // 直接按权重增加周数，不检查 IP
+    let previous_count = data.week_count;
+    data.week_count = add_units(tree, previous_count, pending.units as i128)?;
+
+    // 保存到数据库（事务的一部分）
+    let serialized = bincode::serialize(&data).map_err(abort)?;
+    tree.insert(key, serialized)?;
+
+    // 写入历史记录（同一事务内）
+    let entry = HistoryEntry {
+        time: pending.now,
+        week_count: data.week_count,
+        ip: None,
+        source: pending.source.clone(),
+        units: pending.units,
+        country: None,
+    };
+    let serialized = bincode::serialize(&entry).map_err(abort)?;
+    history.insert(&history_key(pending.now, pending.history_id), serialized)?;
+
+    // 累加每日、本期、累计和来源计数
+    bump_counter(daily, pending.day.as_bytes())?;
+    bump_counter(tree, PERIOD_COUNT_KEY)?;
+    bump_counter(tree, INCREMENTS_TOTAL_KEY)?;
+    bump_counter(sources, pending.source.as_bytes())?;
+
+    // 返回增加前后的周数
+    Ok((previous_count, data.week_count))
+}
+
+/// 记录一次事务尝试，超过上限时中止事务并返回 DbError::Contention
+fn check_attempts(
+    attempts: &Cell<u32>,
+    max_attempts: u32,
+) -> Result<(), ConflictableTransactionError<DbError>> {
+    if attempts.get() >= max_attempts {
+        return Err(abort(DbError::Contention(attempts.get())));
+    }
+    attempts.set(attempts.get() + 1);
+    Ok(())
+}
+
+/// 当前周数的读穿透缓存
+/// 周数只会通过增加而变大，写入时取较大值，避免并发增加时旧值覆盖新值
+/// 重置等非递增修改会使代数加一，代数变化前开始的读取或增加不再写入缓存，避免装回旧的较大值
+#[derive(Default)]
+struct CountCache {
+    value: RwLock<Option<u64>>,
+    /// 缓存代数，每次失效时加一（在持有 `value` 写锁时修改）
+    generation: AtomicU64,
+    /// 最后一次成功读取或写入的周数，缓存失效后仍保留，数据库出错时作为降级结果
+    last_known: RwLock<Option<u64>>,
+}
+
+impl CountCache {
+    fn get(&self) -> Option<u64> {
+        *self.value.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn last_known(&self) -> Option<u64> {
+        *self.last_known.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 当前缓存代数，应在开始读取或增加前获取，之后传给 `advance`
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// 用新读取或增加后的周数更新缓存
+    /// `generation` 为操作开始前的代数，期间缓存失效过时不更新
+    fn advance(&self, generation: u64, week_count: u64) {
+        let mut value = self.value.write().unwrap_or_else(|e| e.into_inner());
+        if self.generation.load(Ordering::Acquire) != generation {
+            return;
+        }
+        let week_count = value.map_or(week_count, |cached| cached.max(week_count));
+        *value = Some(week_count);
+        *self.last_known.write().unwrap_or_else(|e| e.into_inner()) = Some(week_count);
+    }
+
+    /// 周数被重置等非递增修改后调用
+    fn invalidate(&self) {
+        let mut value = self.value.write().unwrap_or_else(|e| e.into_inner());
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        *value = None;
+    }
+}
+
 /// 异步数据库管理器
 /// 使用 tokio 任务将阻塞的数据库操作移到后台线程池
 pub struct Database {
     db: Arc<Db>,
     week_tree: Arc<Tree>,
     click_tree: Arc<Tree>,
+    user_agent_tree: Arc<Tree>,
+    history_tree: Arc<Tree>,
+    ip_count_tree: Arc<Tree>,
+    daily_tree: Arc<Tree>,
+    archive_tree: Arc<Tree>,
+    idempotency_tree: Arc<Tree>,
+    source_tree: Arc<Tree>,
+    /// 首页访问按国家的计数（仅在启用 GeoIP 时写入）
+    country_tree: Arc<Tree>,
+    /// 按钮点击的 IP 最后点击时间，与首页访问的去重记录分开保存
+    button_click_tree: Arc<Tree>,
+    count_cache: CountCache,
+    hooks: HookRegistry,
+    /// 时间来源
+    clock: Arc<dyn Clock>,
+    /// IP 所属国家的查询来源，未设置时不统计国家
+    geoip: Option<Arc<dyn CountryLookup>>,
+    /// 限制同时进入阻塞线程池的数据库操作数
+    blocking_permits: Arc<Semaphore>,
+    /// 许可总数
+    max_blocking_ops: usize,
+    /// 单次数据库操作（含排队等待）的超时时间
+    op_timeout: std::time::Duration,
+    /// 增加周数事务的最大尝试次数
+    max_tx_attempts: u32,
+    /// 冻结时间，到达后所有增加周数的入口都不再计数
+    freeze_at: Option<DateTime<Utc>>,
 }
 
 impl Database {
     /// 创建新的数据库实例
-    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let db = sled::open(path)?;
+    /// 打开前会先检查数据目录是否可写，避免之后写入时静默失败
+    /// `initial_count` 只在数据库中还没有周数时作为初始周数写入
+    pub fn new(path: &str, initial_count: u64) -> Result<Self, DbError> {
+        check_data_dir(Path::new(path))?;
+        Self::open(sled::open(path)?, initial_count)
+    }
+
+    /// 在已打开的 sled 实例上完成启动时的初始化
+    fn open(db: Db, initial_count: u64) -> Result<Self, DbError> {
+        let database = Self::from_db(db)?;
+        database.apply_initial_count(initial_count)?;
+        database.seed_increments_total()?;
+        Ok(database)
+    }
+
+    /// 累计增加次数不存在时（计数器加入之前创建的数据库）以历史记录条数作为初始值
+    fn seed_increments_total(&self) -> Result<(), DbError> {
+        let total = self.history_tree.len() as u64;
+        let swapped = self.week_tree.compare_and_swap(
+            INCREMENTS_TOTAL_KEY,
+            None as Option<&[u8]>,
+            Some(&total.to_be_bytes()),
+        )?;
+        if swapped.is_ok() {
+            log::info!("累计增加次数初始化为 {}", total);
+        }
+        Ok(())
+    }
+
+    /// 周数不存在时写入初始周数，已有周数时不做任何修改
+    fn apply_initial_count(&self, initial_count: u64) -> Result<(), DbError> {
+        if initial_count == 0 {
+            return Ok(());
+        }
+
+        let data = WeekData {
+            week_count: initial_count,
+            last_click_time: None,
+        };
+        let swapped = self.week_tree.compare_and_swap(
+            b"current_week",
+            None as Option<&[u8]>,
+            Some(bincode::serialize(&data)?),
+        )?;
+        if swapped.is_ok() {
+            self.week_tree.flush()?;
+            log::info!("新数据库，周数初始化为 {}", initial_count);
+        }
+        Ok(())
+    }
+
+    /// 从已打开的 sled 实例构建数据库
+    fn from_db(db: Db) -> Result<Self, DbError> {
         let week_tree = db.open_tree("weeks")?;
         let click_tree = db.open_tree("clicks")?;
+        let user_agent_tree = db.open_tree("user_agents")?;
+        let history_tree = db.open_tree("history")?;
+        let ip_count_tree = db.open_tree("ip_counts")?;
+        let daily_tree = db.open_tree("daily")?;
+        let archive_tree = db.open_tree("archive")?;
+        let idempotency_tree = db.open_tree("idempotency")?;
+        let source_tree = db.open_tree("sources")?;
+        let country_tree = db.open_tree("countries")?;
+        let button_click_tree = db.open_tree("button_clicks")?;
 
         Ok(Database {
             db: Arc::new(db),
             week_tree: Arc::new(week_tree),
             click_tree: Arc::new(click_tree),
+            user_agent_tree: Arc::new(user_agent_tree),
+            history_tree: Arc::new(history_tree),
+            ip_count_tree: Arc::new(ip_count_tree),
+            daily_tree: Arc::new(daily_tree),
+            archive_tree: Arc::new(archive_tree),
+            idempotency_tree: Arc::new(idempotency_tree),
+            source_tree: Arc::new(source_tree),
+            country_tree: Arc::new(country_tree),
+            button_click_tree: Arc::new(button_click_tree),
+            count_cache: CountCache::default(),
+            hooks: HookRegistry::new(),
+            clock: Arc::new(SystemClock),
+            geoip: None,
+            blocking_permits: Arc::new(Semaphore::new(DEFAULT_MAX_BLOCKING_OPS)),
+            max_blocking_ops: DEFAULT_MAX_BLOCKING_OPS,
+            op_timeout: DEFAULT_OP_TIMEOUT,
+            max_tx_attempts: DEFAULT_MAX_TX_ATTEMPTS,
+            freeze_at: None,
         })
     }
 
+    /// 设置增加周数事务的最大尝试次数
+    pub fn with_max_tx_attempts(mut self, max_tx_attempts: u32) -> Self {
+        self.max_tx_attempts = max_tx_attempts;
+        self
+    }
+
+    /// 设置成功增加周数后调用的钩子
+    pub fn with_hooks(mut self, hooks: HookRegistry) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// 设置同时进行的阻塞数据库操作数上限，超出的请求排队等待
+    pub fn with_max_blocking_ops(mut self, max_blocking_ops: usize) -> Self {
+        self.max_blocking_ops = max_blocking_ops.max(1);
+        self.blocking_permits = Arc::new(Semaphore::new(self.max_blocking_ops));
+        self
+    }
+
+    /// 当前阻塞数据库操作的并发状态
+    pub fn blocking_stats(&self) -> BlockingStats {
+        let available_permits = self.blocking_permits.available_permits();
+        BlockingStats {
+            max_blocking_ops: self.max_blocking_ops,
+            available_permits,
+            in_flight: self.max_blocking_ops.saturating_sub(available_permits),
+        }
+    }
+
+    /// 设置单次数据库操作的超时时间
+    pub fn with_op_timeout(mut self, op_timeout: std::time::Duration) -> Self {
+        self.op_timeout = op_timeout;
+        self
+    }
+
+    /// 设置冻结时间，到达后增加周数变为不计数的空操作，读取不受影响
+    pub fn with_freeze_at(mut self, freeze_at: Option<DateTime<Utc>>) -> Self {
+        self.freeze_at = freeze_at;
+        self
+    }
+
+    /// 数据库时钟的当前时间，处理函数需要与数据库一致的时间时使用
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// 检查在给定时间是否已冻结，所有增加周数的入口都先经过这里
+    fn is_frozen(&self, now: DateTime<Utc>) -> bool {
+        self.freeze_at.is_some_and(|freeze_at| now >= freeze_at)
+    }
+
+    /// 在阻塞线程池中执行数据库操作
+    /// 先获取许可，避免请求洪峰耗尽阻塞线程池、拖垮整个服务
+    /// 排队和执行总共超过超时时间时返回 DbError::Timeout（已开始的阻塞操作会在后台继续完成）
+    /// 许可随闭包移入阻塞任务，超时后直到阻塞操作真正结束才释放，卡住的操作不会突破并发上限
+    async fn run_blocking<T, F>(&self, f: F) -> Result<T, DbError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, DbError> + Send + 'static,
+    {
+        let permits = self.blocking_permits.clone();
+        let operation = async move {
+            // 信号量不会被关闭，获取许可不会失败
+            let permit = permits
+                .acquire_owned()
+                .await
+                .expect("blocking semaphore closed");
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                f()
+            })
+            .await?
+        };
+        tokio::time::timeout(self.op_timeout, operation)
+            .await
+            .map_err(|_| DbError::Timeout(self.op_timeout))?
+    }
+
+    /// 设置时间来源，默认使用系统时钟
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 设置 IP 所属国家的查询来源，设置后首页访问会按国家计数
+    pub fn with_geoip(mut self, geoip: Option<Arc<dyn CountryLookup>>) -> Self {
+        self.geoip = geoip;
+        self
+    }
+
+    /// 创建临时数据库（仅用于测试）
+    #[cfg(test)]
+    pub fn temporary() -> Result<Self, DbError> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Self::from_db(db)
+    }
+
     /// 异步获取当前周数
+    /// 优先读取缓存，未命中时读取数据库并写入缓存
     pub async fn get_week_count(&self) -> Result<u64, DbError> {
+        if let Some(week_count) = self.count_cache.get() {
+            return Ok(week_count);
+        }
+
+        let generation = self.count_cache.generation();
         let week_tree = self.week_tree.clone();
-        tokio::task::spawn_blocking(move || {
+        let week_count = self.run_blocking(move || {
             let key = b"current_week";
             if let Some(value) = week_tree.get(key)? {
                 let data: WeekData = bincode::deserialize(&value)?;
-                Ok(data.week_count)
+                Ok::<_, DbError>(data.week_count)
             } else {
                 Ok(0)
             }
         })
-        .await?
+        .await?;
+
+        self.count_cache.advance(generation, week_count);
+        Ok(week_count)
+    }
+
+    /// 异步获取周数，读取失败时退回最后一次已知的周数
+    /// 返回值中的布尔值表示结果是否来自降级（可能已过时）；没有已知值时仍返回错误
+    pub async fn get_week_count_or_stale(&self) -> Result<(u64, bool), DbError> {
+        match self.get_week_count().await {
+            Ok(week_count) => Ok((week_count, false)),
+            Err(e) => match self.count_cache.last_known() {
+                Some(week_count) => {
+                    log::warn!("读取周数失败，返回最后已知的周数 {}: {}", week_count, e);
+                    Ok((week_count, true))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// 预热周数缓存，使启动后的第一个请求无需读取数据库
+    pub async fn warm_cache(&self) -> Result<u64, DbError> {
+        self.count_cache.invalidate();
+        self.get_week_count().await
+    }
+
+    /// 异步检查 IP 在当前时间窗口内是否已经计数过（不修改任何数据）
+    pub async fn check_ip_window(&self, ip: &str) -> Result<bool, DbError> {
+        let click_tree = self.click_tree.clone();
+        let ip_key = format!("ip:{}", ip);
+        let now = self.clock.now();
+        self.run_blocking(move || ip_counted_in_window(&click_tree, &ip_key, now)).await
+    }
+
+    /// 异步记录一次按钮点击的 IP，返回该 IP 在当前时间窗口（同一 UTC 日）内是否已经点击过
+    /// 只记录按钮点击，首页访问不影响结果
+    pub async fn record_button_click(&self, ip: &str) -> Result<bool, DbError> {
+        let button_click_tree = self.button_click_tree.clone();
+        let ip = ip.to_string();
+        let now = self.clock.now();
+        self.run_blocking(move || {
+            // 写入并取回上一次的点击时间是原子的，同一 IP 的并发点击只有一个不算重复
+            match button_click_tree.insert(ip.as_bytes(), now.to_rfc3339().as_bytes())? {
+                Some(prev_click) => click_in_window(&prev_click, now),
+                None => Ok(false),
+            }
+        })
+        .await
     }
 
     /// 异步增加周数（带 IP 检查，用于首页访问）
     /// 使用事务确保原子性，防止并发情况下的数据竞争
-    pub async fn increment_week_with_ip_check(&self, ip: String) -> Result<bool, DbError> {
+    /// 提供 `user_agent` 时会截断后与 IP 访问时间一起保存，`source` 为历史记录中的来源标签
+    pub async fn increment_week_with_ip_check(
+        &self,
+        ip: String,
+        user_agent: Option<String>,
+        source: Option<&str>,
+    ) -> Result<bool, DbError> {
+        let db = self.db.clone();
         let week_tree = self.week_tree.clone();
         let click_tree = self.click_tree.clone();
-        let now = Utc::now();
+        let user_agent_tree = self.user_agent_tree.clone();
+        let history_tree = self.history_tree.clone();
+        let ip_count_tree = self.ip_count_tree.clone();
+        let daily_tree = self.daily_tree.clone();
+        let source_tree = self.source_tree.clone();
+        let country_tree = self.country_tree.clone();
+        let geoip = self.geoip.clone();
+        let max_attempts = self.max_tx_attempts;
+        let now = self.clock.now();
+        let outcome_ip = ip.clone();
+        let source = source.map(|source| source.to_string());
+        if self.is_frozen(now) {
+            return Ok(false);
+        }
 
-        tokio::task::spawn_blocking(move || {
+        let generation = self.count_cache.generation();
+        let new_week_count = self.run_blocking(move || {
             let week_key = b"current_week";
             let ip_key = format!("ip:{}", ip);
 
-            // 先检查 IP 是否在当天已经访问过（这个检查不需要在事务中）
+            // 快速路径：同一天内已经访问过的 IP 无需进入事务
             let ip_bytes = ip_key.as_bytes();
-            if let Some(prev_click_bytes) = click_tree.get(ip_bytes)? {
-                let prev_click_str = std::str::from_utf8(&prev_click_bytes)?;
-                let prev_click = DateTime::parse_from_rfc3339(prev_click_str)?;
-                let prev_date = prev_click.date_naive();
-                let current_date = now.date_naive();
-
-                if prev_date == current_date {
-                    // 同一天内已经访问过
-                    return Ok(false);
-                }
+            if ip_counted_in_window(&click_tree, &ip_key, now)? {
+                return Ok(None);
             }
 
-            // 使用事务更新周数（确保并发安全）
-            let new_week_count = week_tree.transaction(|tree| {
+            // 使用事务检查并记录 IP 访问时间、更新周数、写入历史记录并累加每日、来源和该 IP 的计数
+            // 去重检查与记录在同一事务中，同一 IP 的并发请求只有一个能计数
+            let pending = PendingIncrement::new(&db, now, source.as_deref(), COUNT_UNIT)?;
+            // 查询不到国家时照常计数，只是不归属国家
+            let country = geoip.as_ref().and_then(|geoip| geoip.country(&ip));
+            let attempts = Cell::new(0);
+            let trees = (
+                &*week_tree,
+                &*history_tree,
+                &*ip_count_tree,
+                &*daily_tree,
+                &*source_tree,
+                &*click_tree,
+                &*user_agent_tree,
+                &*country_tree,
+            );
+            let new_week_count = trees.transaction(|(tree, history, ip_counts, daily, sources, clicks, user_agents, countries)| {
+                // 超过重试上限时放弃，避免在激烈竞争下无限重试
+                check_attempts(&attempts, max_attempts)?;
+
+                // 再次检查 IP 是否在当天已经访问过
+                if let Some(prev_click) = clicks.get(ip_bytes)? {
+                    if click_in_window(&prev_click, now).map_err(abort)? {
+                        return Ok(None);
+                    }
+                }
+
                 // 获取当前数据
                 let mut data: WeekData = if let Some(value) = tree.get(week_key)? {
-                    bincode::deserialize(&value).map_err(|e| {
-                        sled::transaction::ConflictableTransactionError::Abort(
-                            sled::Error::Unsupported(e.to_string())
-                        )
-                    })?
+                    bincode::deserialize(&value).map_err(abort)?
                 } else {
                     WeekData {
                         week_count: 0,
@@ -108,153 +985,2032 @@ impl Database {
                 };
 
                 // 增加周数
-                data.week_count += 1;
+                let previous_count = data.week_count;
+                data.week_count = add_units(tree, previous_count, COUNT_UNIT as i128)?;
                 data.last_click_time = Some(now);
 
                 // 保存
-                let serialized = bincode::serialize(&data).map_err(|e| {
-                    sled::transaction::ConflictableTransactionError::Abort(
-                        sled::Error::Unsupported(e.to_string())
-                    )
-                })?;
+                let serialized = bincode::serialize(&data).map_err(abort)?;
                 tree.insert(week_key, serialized)?;
 
-                Ok(data.week_count)
-            })
-            .map_err(|e| match e {
-                sled::transaction::TransactionError::Abort(err) => {
-                    DbError::Sled(err)
+                // 写入历史记录
+                let entry = HistoryEntry {
+                    time: now,
+                    week_count: data.week_count,
+                    ip: Some(ip.clone()),
+                    source: pending.source.clone(),
+                    units: pending.units,
+                    country: country.clone(),
+                };
+                let serialized = bincode::serialize(&entry).map_err(abort)?;
+                history.insert(&history_key(now, pending.history_id), serialized)?;
+
+                // 累加每日、本期、累计、来源和该 IP 的计数
+                bump_counter(daily, pending.day.as_bytes())?;
+                bump_counter(tree, PERIOD_COUNT_KEY)?;
+                bump_counter(tree, INCREMENTS_TOTAL_KEY)?;
+                bump_counter(sources, pending.source.as_bytes())?;
+                bump_counter(ip_counts, ip.as_bytes())?;
+                if let Some(country) = &country {
+                    bump_counter(countries, country.as_bytes())?;
                 }
-                sled::transaction::TransactionError::Storage(err) => {
-                    DbError::Sled(err)
+
+                // 记录 IP 访问时间
+                clicks.insert(ip_bytes, now.to_rfc3339().as_bytes())?;
+                if let Some(user_agent) = &user_agent {
+                    user_agents.insert(ip_bytes, truncate_user_agent(user_agent).as_bytes())?;
                 }
-            })?;
 
-            // 记录 IP 访问时间（在事务成功后）
-            click_tree.insert(ip_bytes, now.to_rfc3339().as_bytes())?;
+                Ok(Some((previous_count, data.week_count)))
+            })
+            .map_err(transaction_error)?;
 
-            Ok(new_week_count > 0)
+            Ok::<_, DbError>(new_week_count)
         })
-        .await?
+        .await?;
+
+        match new_week_count {
+            Some((previous_count, week_count)) => {
+                self.count_cache.advance(generation, week_count);
+                self.hooks.dispatch(IncrementOutcome {
+                    previous_count,
+                    week_count,
+                    time: now,
+                    ip: Some(outcome_ip),
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     /// 异步增加周数（无 IP 检查，用于按钮点击）
     /// 使用事务确保原子性，防止并发情况下的数据竞争
-    pub async fn increment_week(&self) -> Result<u64, DbError> {
+    pub async fn increment_week(&self, source: Option<&str>) -> Result<u64, DbError> {
+        self.increment_weighted(1.0, source).await
+    }
+
+    /// 异步按权重增加周数（无 IP 检查），权重限制在 0 到 1 之间
+    /// 周数内部以 COUNT_UNIT 为精度累计，返回四舍五入后的显示周数；权重为 0 时不记录任何数据
+    pub async fn increment_weighted(&self, weight: f64, source: Option<&str>) -> Result<u64, DbError> {
+        let db = self.db.clone();
         let week_tree = self.week_tree.clone();
+        let history_tree = self.history_tree.clone();
+        let daily_tree = self.daily_tree.clone();
+        let source_tree = self.source_tree.clone();
+        let max_attempts = self.max_tx_attempts;
+        let now = self.clock.now();
+        let source = source.map(|source| source.to_string());
+        let units = weight_units(weight);
+        if units == 0 || self.is_frozen(now) {
+            return self.get_week_count().await;
+        }
 
-        tokio::task::spawn_blocking(move || {
-            let key = b"current_week";
+        let generation = self.count_cache.generation();
+        let (previous_count, week_count) = self.run_blocking(move || {
+            let pending = PendingIncrement::new(&db, now, source.as_deref(), units)?;
+            let attempts = Cell::new(0);
 
             // 使用事务确保原子性
-            // Sled 事务会自动重试，直到成功或达到最大重试次数
-            week_tree.transaction(|tree| {
-                // 获取当前数据
-                let mut data: WeekData = if let Some(value) = tree.get(key)? {
-                    // 手动反序列化以处理事务中的错误
-                    bincode::deserialize(&value).map_err(|e| {
-                        sled::transaction::ConflictableTransactionError::Abort(
-                            sled::Error::Unsupported(e.to_string())
-                        )
-                    })?
-                } else {
-                    WeekData {
-                        week_count: 0,
-                        last_click_time: None,
+            // Sled 事务冲突时会自动重试，超过重试上限后返回 DbError::Contention
+            let trees = (&*week_tree, &*history_tree, &*daily_tree, &*source_tree);
+            trees
+                .transaction(|(tree, history, daily, sources)| {
+                    check_attempts(&attempts, max_attempts)?;
+                    apply_increment(tree, history, daily, sources, &pending)
+                })
+                .map_err(transaction_error)
+        })
+        .await?;
+
+        self.count_cache.advance(generation, week_count);
+        // 显示周数没有变化时不通知钩子，避免重复推送同一周数
+        if week_count != previous_count {
+            self.hooks.dispatch(IncrementOutcome {
+                previous_count,
+                week_count,
+                time: now,
+                ip: None,
+            });
+        }
+        Ok(week_count)
+    }
+
+    /// 异步带幂等键按权重增加周数（无 IP 检查，用于按钮点击的客户端重试）
+    /// 同一个键在 `ttl` 内再次请求时不再增加，直接返回首次的结果；返回值中的布尔值表示是否为重放
+    pub async fn increment_week_idempotent(
+        &self,
+        idempotency_key: String,
+        ttl: Duration,
+        weight: f64,
+        source: Option<&str>,
+    ) -> Result<(u64, bool), DbError> {
+        let db = self.db.clone();
+        let week_tree = self.week_tree.clone();
+        let history_tree = self.history_tree.clone();
+        let daily_tree = self.daily_tree.clone();
+        let source_tree = self.source_tree.clone();
+        let idempotency_tree = self.idempotency_tree.clone();
+        let max_attempts = self.max_tx_attempts;
+        let now = self.clock.now();
+        let source = source.map(|source| source.to_string());
+        let units = weight_units(weight);
+        if units == 0 || self.is_frozen(now) {
+            // 权重为 0 或冻结后不再记录幂等键，重试得到的也是当前周数
+            return Ok((self.get_week_count().await?, false));
+        }
+
+        let generation = self.count_cache.generation();
+        let (previous_count, week_count, replayed) = self.run_blocking(move || {
+            let pending = PendingIncrement::new(&db, now, source.as_deref(), units)?;
+            let attempts = Cell::new(0);
+            let trees = (
+                &*week_tree,
+                &*history_tree,
+                &*daily_tree,
+                &*source_tree,
+                &*idempotency_tree,
+            );
+
+            // 键的查询和记录与增加在同一事务中，避免并发重试重复计数
+            trees
+                .transaction(|(tree, history, daily, sources, keys)| {
+                    check_attempts(&attempts, max_attempts)?;
+
+                    if let Some(value) = keys.get(idempotency_key.as_bytes())? {
+                        let record: IdempotencyRecord =
+                            bincode::deserialize(&value).map_err(abort)?;
+                        // 过期的键视为不存在
+                        if now - record.time < ttl {
+                            return Ok((record.week_count, record.week_count, true));
+                        }
                     }
-                };
 
-                // 直接增加周数，不检查 IP
-                data.week_count += 1;
+                    let (previous_count, week_count) = apply_increment(tree, history, daily, sources, &pending)?;
+                    let record = IdempotencyRecord {
+                        time: now,
+                        week_count,
+                    };
+                    let serialized = bincode::serialize(&record).map_err(abort)?;
+                    keys.insert(idempotency_key.as_bytes(), serialized)?;
+                    Ok((previous_count, week_count, false))
+                })
+                .map_err(transaction_error)
+        })
+        .await?;
+
+        if !replayed {
+            self.count_cache.advance(generation, week_count);
+        }
+        if week_count != previous_count {
+            self.hooks.dispatch(IncrementOutcome {
+                previous_count,
+                week_count,
+                time: now,
+                ip: None,
+            });
+        }
+        Ok((week_count, replayed))
+    }
 
-                // 保存到数据库（事务的一部分）
-                let serialized = bincode::serialize(&data).map_err(|e| {
-                    sled::transaction::ConflictableTransactionError::Abort(
-                        sled::Error::Unsupported(e.to_string())
-                    )
-                })?;
-                tree.insert(key, serialized)?;
+    /// 异步查询幂等键在 `ttl` 内记录的首次结果，键不存在或已过期时返回 None
+    /// 只用于提前返回重放结果，真正的增加仍由 `increment_week_idempotent` 在事务中检查
+    pub async fn idempotent_replay(&self, idempotency_key: &str, ttl: Duration) -> Result<Option<u64>, DbError> {
+        let idempotency_tree = self.idempotency_tree.clone();
+        let idempotency_key = idempotency_key.to_string();
+        let now = self.clock.now();
+        self.run_blocking(move || {
+            let Some(value) = idempotency_tree.get(idempotency_key.as_bytes())? else {
+                return Ok(None);
+            };
+            let record: IdempotencyRecord = bincode::deserialize(&value)?;
+            Ok((now - record.time < ttl).then_some(record.week_count))
+        })
+        .await
+    }
 
-                // 返回新的周数
-                Ok(data.week_count)
-            })
-            .map_err(|e| match e {
-                sled::transaction::TransactionError::Abort(err) => {
-                    // 事务被中止，转换错误类型
-                    DbError::Sled(err)
-                }
-                sled::transaction::TransactionError::Storage(err) => {
-                    // 存储错误，转换错误类型
-                    DbError::Sled(err)
+    /// 异步删除早于指定时间的幂等键，返回删除的数量
+    pub async fn prune_idempotency_keys(&self, older_than: DateTime<Utc>) -> Result<usize, DbError> {
+        let idempotency_tree = self.idempotency_tree.clone();
+        self.run_blocking(move || {
+            let mut pruned = 0;
+            for item in idempotency_tree.iter() {
+                let (key, value) = item?;
+                let record: IdempotencyRecord = bincode::deserialize(&value)?;
+                if record.time < older_than {
+                    // 只删除仍是这条记录的键，避免误删期间被重新写入的键
+                    if idempotency_tree
+                        .compare_and_swap(&key, Some(value), None as Option<&[u8]>)?
+                        .is_ok()
+                    {
+                        pruned += 1;
+                    }
                 }
-            })
+            }
+            Ok(pruned)
         })
-        .await?
+        .await
     }
 
-    /// 异步获取完整的周数据信息
-    pub async fn get_week_data(&self) -> Result<WeekData, DbError> {
-        let week_tree = self.week_tree.clone();
-        tokio::task::spawn_blocking(move || {
-            let key = b"current_week";
-            if let Some(value) = week_tree.get(key)? {
-                let data: WeekData = bincode::deserialize(&value)?;
-                Ok(data)
-            } else {
-                Ok(WeekData {
-                    week_count: 0,
-                    last_click_time: None,
-                })
+    /// 异步删除不在当前时间窗口（同一 UTC 日）内的按钮点击记录，返回删除的数量
+    /// 这些记录已不影响加权，删除后不再保存过期的 IP；无法解析的记录也一并删除
+    pub async fn prune_button_clicks(&self) -> Result<usize, DbError> {
+        let button_click_tree = self.button_click_tree.clone();
+        let now = self.clock.now();
+        self.run_blocking(move || {
+            let mut pruned = 0;
+            for item in button_click_tree.iter() {
+                let (key, value) = item?;
+                if !matches!(click_in_window(&value, now), Ok(true)) {
+                    // 只删除仍是这条记录的键，避免误删期间重新点击写入的记录
+                    if button_click_tree
+                        .compare_and_swap(&key, Some(value), None as Option<&[u8]>)?
+                        .is_ok()
+                    {
+                        pruned += 1;
+                    }
+                }
             }
+            Ok(pruned)
         })
-        .await?
+        .await
     }
 
-    /// 异步重置周数（用于测试或管理）
-    pub async fn reset_weeks(&self) -> Result<(), DbError> {
+    /// 异步读取本期增加次数并清零（同一事务内完成），用于定期报告
+    /// 次数按事件计，不考虑权重
+    /// 与增加周数在同一棵树上通过事务串行化，跨报告边界的增加不会重复或遗漏
+    pub async fn take_period_count(&self) -> Result<u64, DbError> {
         let week_tree = self.week_tree.clone();
-        tokio::task::spawn_blocking(move || {
-            let key = b"current_week";
-            week_tree.remove(key)?;
-            let _ = week_tree.flush()?;
-            Ok(())
+        self.run_blocking(move || {
+            week_tree
+                .transaction(|tree| {
+                    let count = tree.get(PERIOD_COUNT_KEY)?.map(|v| decode_u64(&v)).unwrap_or(0);
+                    tree.remove(PERIOD_COUNT_KEY)?;
+                    Ok(count)
+                })
+                .map_err(transaction_error)
         })
-        .await?
+        .await
     }
 
-    /// 异步刷新数据库到磁盘
-    pub fn flush_async(&self) -> JoinHandle<Result<(), DbError>> {
+    /// 异步按给定量修正周数（不低于 0），并在同一事务中写入带原因的档案记录
+    pub async fn adjust_week(&self, delta: i64, reason: &str) -> Result<Adjustment, DbError> {
+        let db = self.db.clone();
         let week_tree = self.week_tree.clone();
-        tokio::task::spawn_blocking(move || {
+        let archive_tree = self.archive_tree.clone();
+        let reason = reason.to_string();
+        let now = self.clock.now();
+
+        let adjustment = self.run_blocking(move || {
+            let archive_id = db.generate_id()?;
+            (&*week_tree, &*archive_tree)
+                .transaction(|(tree, archive)| {
+                    let mut data: WeekData = match tree.get(b"current_week")? {
+                        Some(value) => bincode::deserialize(&value).map_err(abort)?,
+                        None => WeekData {
+                            week_count: 0,
+                            last_click_time: None,
+                        },
+                    };
+
+                    let previous_count = data.week_count;
+                    data.week_count = add_units(tree, previous_count, delta as i128 * COUNT_UNIT as i128)?;
+                    let applied = data.week_count as i128 - previous_count as i128;
+                    let applied = applied.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+
+                    let serialized = bincode::serialize(&data).map_err(abort)?;
+                    tree.insert(b"current_week", serialized)?;
+
+                    let entry = ArchiveEntry {
+                        time: now,
+                        action: ArchiveAction::Adjust {
+                            delta,
+                            applied,
+                            previous_count,
+                            week_count: data.week_count,
+                            reason: reason.clone(),
+                        },
+                    };
+                    let serialized = bincode::serialize(&entry).map_err(abort)?;
+                    archive.insert(&history_key(now, archive_id), serialized)?;
+
+                    Ok(Adjustment {
+                        previous_count,
+                        week_count: data.week_count,
+                        applied,
+                    })
+                })
+                .map_err(transaction_error)
+        })
+        .await?;
+
+        // 周数可能减少，丢弃缓存
+        self.count_cache.invalidate();
+        if adjustment.week_count > adjustment.previous_count {
+            self.hooks.dispatch(IncrementOutcome {
+                previous_count: adjustment.previous_count,
+                week_count: adjustment.week_count,
+                time: now,
+                ip: None,
+            });
+        }
+        Ok(adjustment)
+    }
+
+    /// 异步按时间倒序列出档案记录
+    #[cfg(test)]
+    pub async fn list_archive(&self) -> Result<Vec<ArchiveEntry>, DbError> {
+        let archive_tree = self.archive_tree.clone();
+        self.run_blocking(move || {
+            let mut entries = Vec::new();
+            for item in archive_tree.iter().rev() {
+                let (_, value) = item?;
+                entries.push(bincode::deserialize(&value)?);
+            }
+            Ok(entries)
+        })
+        .await
+    }
+
+    /// 按名称查找树，`week` 为 `weeks` 的别名
+    fn tree_by_name(&self, name: &str) -> Option<Arc<Tree>> {
+        let tree = match name {
+            "week" | "weeks" => &self.week_tree,
+            "clicks" => &self.click_tree,
+            "user_agents" => &self.user_agent_tree,
+            "history" => &self.history_tree,
+            "ip_counts" => &self.ip_count_tree,
+            "daily" => &self.daily_tree,
+            "archive" => &self.archive_tree,
+            "idempotency" => &self.idempotency_tree,
+            "sources" => &self.source_tree,
+            "countries" => &self.country_tree,
+            "button_clicks" => &self.button_click_tree,
+            _ => return None,
+        };
+        Some(tree.clone())
+    }
+
+    /// 异步读取指定树中某个键的原始字节，用于调试序列化问题
+    pub async fn get_raw(&self, tree: &str, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let tree = self
+            .tree_by_name(tree)
+            .ok_or_else(|| DbError::UnknownTree(tree.to_string()))?;
+        let key = key.to_string();
+        self.run_blocking(move || {
+            Ok(tree.get(key.as_bytes())?.map(|value| value.to_vec()))
+        })
+        .await
+    }
+
+    /// 异步导出所有树的快照
+    pub async fn export_snapshot(&self) -> Result<Snapshot, DbError> {
+        let trees: Vec<_> = SNAPSHOT_TREES
+            .iter()
+            .filter_map(|name| Some((name.to_string(), self.tree_by_name(name)?)))
+            .collect();
+        self.run_blocking(move || {
+            let mut snapshot = Snapshot {
+                version: SNAPSHOT_VERSION,
+                trees: BTreeMap::new(),
+            };
+            for (name, tree) in trees {
+                let mut entries = Vec::new();
+                for item in tree.iter() {
+                    let (key, value) = item?;
+                    entries.push((encode_hex(&key), encode_hex(&value)));
+                }
+                snapshot.trees.insert(name, entries);
+            }
+            Ok(snapshot)
+        })
+        .await
+    }
+
+    /// 异步从快照恢复数据：先校验版本和全部内容，再逐棵树整体替换（每棵树的替换是原子的）
+    /// 快照中没有出现的树保持不变
+    pub async fn import_snapshot(&self, snapshot: Snapshot) -> Result<(), DbError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(DbError::InvalidSnapshot(format!(
+                "不支持的版本 {}（当前为 {}）",
+                snapshot.version, SNAPSHOT_VERSION
+            )));
+        }
+
+        let mut batches = Vec::new();
+        for (name, entries) in snapshot.trees {
+            let tree = self
+                .tree_by_name(&name)
+                .filter(|_| SNAPSHOT_TREES.contains(&name.as_str()))
+                .ok_or_else(|| DbError::UnknownTree(name.clone()))?;
+            let mut decoded = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                match (decode_hex(&key), decode_hex(&value)) {
+                    (Some(key), Some(value)) => decoded.push((key, value)),
+                    _ => {
+                        return Err(DbError::InvalidSnapshot(format!(
+                            "树 {} 中的键 {} 不是合法的十六进制",
+                            name, key
+                        )))
+                    }
+                }
+            }
+            batches.push((tree, decoded));
+        }
+
+        let week_tree = self.week_tree.clone();
+        let result = self.run_blocking(move || {
+            let increments_total = week_tree.get(INCREMENTS_TOTAL_KEY)?.map(|v| decode_u64(&v));
+            for (tree, entries) in batches {
+                let mut batch = sled::Batch::default();
+                for item in tree.iter().keys() {
+                    batch.remove(item?);
+                }
+                for (key, value) in entries {
+                    batch.insert(key, value);
+                }
+                tree.apply_batch(batch)?;
+            }
+
+            // 累计增加次数只增不减，导入较早的快照时保留当前值
+            if let Some(increments_total) = increments_total {
+                week_tree.fetch_and_update(INCREMENTS_TOTAL_KEY, |imported| {
+                    let imported = imported.map(decode_u64).unwrap_or(0);
+                    Some(imported.max(increments_total).to_be_bytes().to_vec())
+                })?;
+            }
+            Ok(())
+        })
+        .await;
+
+        // 周数可能变化，丢弃缓存
+        self.count_cache.invalidate();
+        result
+    }
+
+    /// 异步获取每个来源的增加次数（按事件计，不考虑权重），按次数从多到少排序
+    pub async fn get_source_breakdown(&self) -> Result<Vec<(String, u64)>, DbError> {
+        let source_tree = self.source_tree.clone();
+        self.run_blocking(move || {
+            let mut breakdown = Vec::new();
+            for item in source_tree.iter() {
+                let (key, value) = item?;
+                breakdown.push((std::str::from_utf8(&key)?.to_string(), decode_u64(&value)));
+            }
+            breakdown.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            Ok(breakdown)
+        })
+        .await
+    }
+
+    /// 异步获取每个国家的首页访问次数，按次数从多到少排序
+    pub async fn get_country_breakdown(&self) -> Result<Vec<(String, u64)>, DbError> {
+        let country_tree = self.country_tree.clone();
+        self.run_blocking(move || {
+            let mut breakdown = Vec::new();
+            for item in country_tree.iter() {
+                let (key, value) = item?;
+                breakdown.push((std::str::from_utf8(&key)?.to_string(), decode_u64(&value)));
+            }
+            breakdown.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            Ok(breakdown)
+        })
+        .await
+    }
+
+    /// 异步按一天中的小时统计全部历史记录的增加次数（按事件计，不考虑权重）
+    /// 小时按 `offset` 时区计算，没有增加的小时为 0
+    pub async fn increments_by_hour(&self, offset: FixedOffset) -> Result<[u64; 24], DbError> {
+        let history_tree = self.history_tree.clone();
+        self.run_blocking(move || {
+            let mut hours = [0u64; 24];
+            for item in history_tree.iter() {
+                let (_, value) = item?;
+                let entry: HistoryEntry = bincode::deserialize(&value)?;
+                hours[entry.time.with_timezone(&offset).hour() as usize] += 1;
+            }
+            Ok(hours)
+        })
+        .await
+    }
+
+    /// 异步查询某个 IP 的记录：累计次数、最后访问时间和 User-Agent
+    pub async fn ip_info(&self, ip: &str) -> Result<IpInfo, DbError> {
+        let ip_count_tree = self.ip_count_tree.clone();
+        let click_tree = self.click_tree.clone();
+        let user_agent_tree = self.user_agent_tree.clone();
+        let ip = ip.to_string();
+        self.run_blocking(move || {
+            let ip_key = format!("ip:{}", ip);
+            let count = ip_count_tree.get(ip.as_bytes())?.map(|v| decode_u64(&v)).unwrap_or(0);
+            let last_click_time = match click_tree.get(ip_key.as_bytes())? {
+                Some(value) => Some(
+                    DateTime::parse_from_rfc3339(std::str::from_utf8(&value)?)?.with_timezone(&Utc),
+                ),
+                None => None,
+            };
+            let user_agent = match user_agent_tree.get(ip_key.as_bytes())? {
+                Some(value) => Some(std::str::from_utf8(&value)?.to_string()),
+                None => None,
+            };
+
+            Ok(IpInfo {
+                count,
+                last_click_time,
+                user_agent,
+            })
+        })
+        .await
+    }
+
+    /// 异步查询某个 IP 累计增加的次数，未知 IP 返回 0
+    pub async fn ip_increment_count(&self, ip: &str) -> Result<u64, DbError> {
+        let ip_count_tree = self.ip_count_tree.clone();
+        let ip = ip.to_string();
+        self.run_blocking(move || {
+            Ok(ip_count_tree.get(ip.as_bytes())?.map(|v| decode_u64(&v)).unwrap_or(0))
+        })
+        .await
+    }
+
+    /// 异步计算最近 N 天（含今天，UTC）的平均每日增加次数（按事件计，不考虑权重）
+    /// 记录不足 N 天时，按第一条记录以来的实际天数求平均
+    pub async fn growth_rate(&self, days: u32) -> Result<f64, DbError> {
+        let daily_tree = self.daily_tree.clone();
+        let today = self.clock.now().date_naive();
+        self.run_blocking(move || {
+            if days == 0 {
+                return Ok(0.0);
+            }
+
+            let first_day = match daily_tree.first()? {
+                Some((key, _)) => NaiveDate::parse_from_str(std::str::from_utf8(&key)?, "%Y-%m-%d")?,
+                None => return Ok(0.0),
+            };
+            let window_start = today - Duration::days(days as i64 - 1);
+            let start = window_start.max(first_day);
+            let available_days = (today - start).num_days() + 1;
+
+            let mut total = 0u64;
+            for item in daily_tree.range(daily_key(start)..=daily_key(today)) {
+                let (_, value) = item?;
+                total += decode_u64(&value);
+            }
+
+            Ok(total as f64 / available_days.max(1) as f64)
+        })
+        .await
+    }
+
+    /// 异步收集指标快照
+    pub async fn metrics_snapshot(&self) -> Result<MetricsSnapshot, DbError> {
+        let week_tree = self.week_tree.clone();
+        let history_tree = self.history_tree.clone();
+        let ip_count_tree = self.ip_count_tree.clone();
+        let daily_tree = self.daily_tree.clone();
+        let today = daily_key(self.clock.now().date_naive());
+        self.run_blocking(move || {
+            let week_count = match week_tree.get(b"current_week")? {
+                Some(value) => bincode::deserialize::<WeekData>(&value)?.week_count,
+                None => 0,
+            };
+            let last_increment_time = match history_tree.last()? {
+                Some((_, value)) => Some(bincode::deserialize::<HistoryEntry>(&value)?.time),
+                None => None,
+            };
+            let increments_today = daily_tree.get(today.as_bytes())?.map(|v| decode_u64(&v)).unwrap_or(0);
+
+            Ok(MetricsSnapshot {
+                week_count,
+                unique_ips: ip_count_tree.len() as u64,
+                increments_total: week_tree
+                    .get(INCREMENTS_TOTAL_KEY)?
+                    .map(|v| decode_u64(&v))
+                    .unwrap_or(0),
+                increments_today,
+                last_increment_time,
+            })
+        })
+        .await
+    }
+
+    /// 异步按时间倒序列出历史记录（带键），`before` 用于翻页
+    pub async fn list_history(
+        &self,
+        limit: usize,
+        before: Option<[u8; 16]>,
+    ) -> Result<Vec<(String, HistoryEntry)>, DbError> {
+        let history_tree = self.history_tree.clone();
+        self.run_blocking(move || {
+            let iter = match before {
+                Some(before) => history_tree.range(..before),
+                None => history_tree.range::<&[u8], _>(..),
+            };
+
+            let mut entries = Vec::new();
+            for item in iter.rev().take(limit) {
+                let (key, value) = item?;
+                entries.push((format_history_key(&key), bincode::deserialize(&value)?));
+            }
+            Ok(entries)
+        })
+        .await
+    }
+
+    /// 异步删除一条历史记录
+    /// `decrement` 为 true 时同一事务内将周数、当日计数、来源计数、国家计数和该 IP 的计数各减一
+    /// 删除会写入档案记录，使 `delta_since` 仍能得到历史时间点的周数
+    /// 记录不存在时返回 None，否则返回被删除的记录和删除后的周数
+    pub async fn delete_history(
+        &self,
+        key: [u8; 16],
+        decrement: bool,
+    ) -> Result<Option<(HistoryEntry, u64)>, DbError> {
+        let db = self.db.clone();
+        let week_tree = self.week_tree.clone();
+        let history_tree = self.history_tree.clone();
+        let archive_tree = self.archive_tree.clone();
+        let ip_count_tree = self.ip_count_tree.clone();
+        let daily_tree = self.daily_tree.clone();
+        let source_tree = self.source_tree.clone();
+        let country_tree = self.country_tree.clone();
+        let now = self.clock.now();
+
+        let result = self.run_blocking(move || {
+            let archive_id = history_key(now, db.generate_id()?);
+            let trees = (
+                &*week_tree,
+                &*history_tree,
+                &*ip_count_tree,
+                &*daily_tree,
+                &*source_tree,
+                &*country_tree,
+                &*archive_tree,
+            );
+            trees
+                .transaction(|(tree, history, ip_counts, daily, sources, countries, archive)| {
+                    let Some(value) = history.remove(&key)? else {
+                        return Ok(None);
+                    };
+                    let entry: HistoryEntry = bincode::deserialize(&value).map_err(abort)?;
+
+                    let mut data: WeekData = match tree.get(b"current_week")? {
+                        Some(value) => bincode::deserialize(&value).map_err(abort)?,
+                        None => WeekData {
+                            week_count: 0,
+                            last_click_time: None,
+                        },
+                    };
+
+                    let previous_count = data.week_count;
+                    if decrement {
+                        data.week_count = add_units(tree, data.week_count, -(entry.units as i128))?;
+                        let serialized = bincode::serialize(&data).map_err(abort)?;
+                        tree.insert(b"current_week", serialized)?;
+
+                        drop_counter(daily, daily_key(entry.time.date_naive()).as_bytes())?;
+                        drop_counter(sources, entry.source.as_bytes())?;
+                        if let Some(ip) = &entry.ip {
+                            drop_counter(ip_counts, ip.as_bytes())?;
+                        }
+                        if let Some(country) = &entry.country {
+                            drop_counter(countries, country.as_bytes())?;
+                        }
+                    }
+
+                    let change = ArchiveEntry {
+                        time: now,
+                        action: ArchiveAction::DeleteHistory {
+                            key,
+                            entry_count: entry.week_count,
+                            previous_count,
+                            week_count: data.week_count,
+                        },
+                    };
+                    let serialized = bincode::serialize(&change).map_err(abort)?;
+                    archive.insert(&archive_id, serialized)?;
+
+                    Ok(Some((entry, data.week_count)))
+                })
+                .map_err(transaction_error)
+        })
+        .await;
+
+        if decrement {
+            // 周数减少，丢弃缓存
+            self.count_cache.invalidate();
+        }
+        result
+    }
+
+    /// 异步从历史记录重建派生统计（每日计数、来源计数、国家计数和每个 IP 的计数）
+    /// 先遍历历史记录汇总，再分别以单个批次整体替换各个统计树，每棵树的替换是原子的
+    /// 重建期间发生的增加可能不会反映在结果中，应在低峰期执行
+    pub async fn rebuild_derived(&self) -> Result<(), DbError> {
+        let history_tree = self.history_tree.clone();
+        let ip_count_tree = self.ip_count_tree.clone();
+        let daily_tree = self.daily_tree.clone();
+        let source_tree = self.source_tree.clone();
+        let country_tree = self.country_tree.clone();
+        self.run_blocking(move || {
+            let mut daily: BTreeMap<String, u64> = BTreeMap::new();
+            let mut sources: BTreeMap<String, u64> = BTreeMap::new();
+            let mut countries: BTreeMap<String, u64> = BTreeMap::new();
+            let mut ip_counts: BTreeMap<String, u64> = BTreeMap::new();
+            let mut entries = 0u64;
+
+            for item in history_tree.iter() {
+                let (_, value) = item?;
+                let entry: HistoryEntry = bincode::deserialize(&value)?;
+                *daily.entry(daily_key(entry.time.date_naive())).or_default() += 1;
+                *sources.entry(entry.source).or_default() += 1;
+                if let Some(country) = entry.country {
+                    *countries.entry(country).or_default() += 1;
+                }
+                if let Some(ip) = entry.ip {
+                    *ip_counts.entry(ip).or_default() += 1;
+                }
+                entries += 1;
+            }
+
+            let (days, ips) = (daily.len(), ip_counts.len());
+            replace_counters(&daily_tree, daily)?;
+            replace_counters(&source_tree, sources)?;
+            replace_counters(&country_tree, countries)?;
+            replace_counters(&ip_count_tree, ip_counts)?;
+
+            log::info!(
+                "已从 {} 条历史记录重建派生统计：{} 天的每日计数，{} 个 IP 的计数",
+                entries,
+                days,
+                ips
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    /// 异步计算从某个时间点以来的周数变化（基于历史记录和档案记录）
+    /// 当时的周数取该时间点之前最后一次变化后的周数：增加记录在历史记录中，
+    /// 修正、重置、撤销重置和删除记录在档案记录中，两者都保存了变化后的周数；
+    /// 之后才删除的历史记录从删除档案中取回
+    pub async fn delta_since(&self, since: DateTime<Utc>) -> Result<WeekDelta, DbError> {
+        let week_tree = self.week_tree.clone();
+        let history_tree = self.history_tree.clone();
+        let archive_tree = self.archive_tree.clone();
+        self.run_blocking(move || {
+            let prefix = history_time_prefix(since);
+
+            // 两种记录的键格式相同，按键比较先后
+            let last_increment = match history_tree.range(..prefix).next_back() {
+                Some(item) => {
+                    let (key, value) = item?;
+                    let entry: HistoryEntry = bincode::deserialize(&value)?;
+                    Some((key, entry.week_count))
+                }
+                None => None,
+            };
+            let last_change = match archive_tree.range(..prefix).next_back() {
+                Some(item) => {
+                    let (key, value) = item?;
+                    let entry: ArchiveEntry = bincode::deserialize(&value)?;
+                    Some((key, entry.action.week_count_after()))
+                }
+                None => None,
+            };
+            // 该时间点之后才被删除、但早于该时间点的历史记录
+            let mut deleted = Vec::new();
+            for item in archive_tree.range(prefix..) {
+                let (_, value) = item?;
+                let entry: ArchiveEntry = bincode::deserialize(&value)?;
+                if let ArchiveAction::DeleteHistory { key, entry_count, .. } = entry.action {
+                    if key[..] < prefix[..] {
+                        deleted.push((sled::IVec::from(&key[..]), entry_count));
+                    }
+                }
+            }
+            let count_then = last_increment
+                .into_iter()
+                .chain(last_change)
+                .chain(deleted)
+                .max_by(|a, b| a.0.cmp(&b.0))
+                .map_or(0, |(_, week_count)| week_count);
+
+            let mut increments = 0;
+            let mut units = 0;
+            for item in history_tree.range(prefix..) {
+                let (_, value) = item?;
+                let entry: HistoryEntry = bincode::deserialize(&value)?;
+                increments += 1;
+                units += entry.units;
+            }
+
+            let count_now = match week_tree.get(b"current_week")? {
+                Some(value) => bincode::deserialize::<WeekData>(&value)?.week_count,
+                None => 0,
+            };
+
+            Ok(WeekDelta {
+                increments,
+                weeks_added: units as f64 / COUNT_UNIT as f64,
+                count_then,
+                count_now,
+            })
+        })
+        .await
+    }
+
+    /// 异步获取完整的周数据信息
+    #[allow(dead_code)]
+    pub async fn get_week_data(&self) -> Result<WeekData, DbError> {
+        let week_tree = self.week_tree.clone();
+        self.run_blocking(move || {
+            let key = b"current_week";
+            if let Some(value) = week_tree.get(key)? {
+                let data: WeekData = bincode::deserialize(&value)?;
+                Ok(data)
+            } else {
+                Ok(WeekData {
+                    week_count: 0,
+                    last_click_time: None,
+                })
+            }
+        })
+        .await
+    }
+
+    /// 异步重置周数，重置前的数据写入档案记录，返回重置前的周数和撤销期限
+    /// `grace` 内可以通过 `undo_reset` 恢复，调用方应传入与撤销时相同的期限
+    pub async fn reset_weeks(&self, grace: Duration) -> Result<ResetOutcome, DbError> {
+        let db = self.db.clone();
+        let week_tree = self.week_tree.clone();
+        let archive_tree = self.archive_tree.clone();
+        let now = self.clock.now();
+        let result = self.run_blocking(move || {
+            let archive_id = history_key(now, db.generate_id()?);
+            let previous_count = (&*week_tree, &*archive_tree)
+                .transaction(|(tree, archive)| {
+                    let previous: WeekData = match tree.remove(b"current_week")? {
+                        Some(value) => bincode::deserialize(&value).map_err(abort)?,
+                        None => WeekData {
+                            week_count: 0,
+                            last_click_time: None,
+                        },
+                    };
+                    let previous_count = previous.week_count;
+                    tree.remove(WEIGHTED_TOTAL_KEY)?;
+
+                    let entry = ArchiveEntry {
+                        time: now,
+                        action: ArchiveAction::Reset { previous },
+                    };
+                    let serialized = bincode::serialize(&entry).map_err(abort)?;
+                    archive.insert(&archive_id, serialized)?;
+                    tree.insert(LAST_RESET_KEY, &archive_id)?;
+                    Ok(previous_count)
+                })
+                .map_err(transaction_error)?;
+            week_tree.flush()?;
+            Ok(ResetOutcome {
+                previous_count,
+                undo_deadline: now + grace,
+            })
+        })
+        .await;
+
+        // 周数不再单调递增，丢弃缓存
+        self.count_cache.invalidate();
+        result
+    }
+
+    /// 异步撤销最近一次重置：重置后 `grace` 内恢复重置前的周数，并保留重置后新增的次数
+    /// 每次重置只能撤销一次，撤销也会写入档案记录
+    pub async fn undo_reset(&self, grace: Duration) -> Result<UndoReset, DbError> {
+        let db = self.db.clone();
+        let week_tree = self.week_tree.clone();
+        let archive_tree = self.archive_tree.clone();
+        let now = self.clock.now();
+        let result = self.run_blocking(move || {
+            let archive_id = history_key(now, db.generate_id()?);
+            (&*week_tree, &*archive_tree)
+                .transaction(|(tree, archive)| {
+                    let Some(reset_key) = tree.get(LAST_RESET_KEY)? else {
+                        return Ok(UndoReset::NothingToUndo);
+                    };
+                    let Some(value) = archive.get(&reset_key)? else {
+                        return Ok(UndoReset::NothingToUndo);
+                    };
+                    let reset: ArchiveEntry = bincode::deserialize(&value).map_err(abort)?;
+                    let ArchiveAction::Reset { previous } = reset.action else {
+                        return Ok(UndoReset::NothingToUndo);
+                    };
+                    if now - reset.time > grace {
+                        return Ok(UndoReset::Expired { reset_at: reset.time });
+                    }
+
+                    let current: WeekData = match tree.get(b"current_week")? {
+                        Some(value) => bincode::deserialize(&value).map_err(abort)?,
+                        None => WeekData {
+                            week_count: 0,
+                            last_click_time: None,
+                        },
+                    };
+                    // 重置后的加权增加保留精确的单位数，重置前不足一周的部分没有存档，不再恢复
+                    let week_count = add_units(
+                        tree,
+                        current.week_count,
+                        previous.week_count as i128 * COUNT_UNIT as i128,
+                    )?;
+                    let restored = WeekData {
+                        week_count,
+                        last_click_time: current.last_click_time.or(previous.last_click_time),
+                    };
+                    tree.insert(b"current_week", bincode::serialize(&restored).map_err(abort)?)?;
+                    tree.remove(LAST_RESET_KEY)?;
+
+                    let entry = ArchiveEntry {
+                        time: now,
+                        action: ArchiveAction::UndoReset {
+                            reset_count: current.week_count,
+                            week_count: restored.week_count,
+                        },
+                    };
+                    let serialized = bincode::serialize(&entry).map_err(abort)?;
+                    archive.insert(&archive_id, serialized)?;
+                    Ok(UndoReset::Restored {
+                        reset_count: current.week_count,
+                        week_count: restored.week_count,
+                    })
+                })
+                .map_err(transaction_error)
+        })
+        .await;
+
+        self.count_cache.invalidate();
+        if let Ok(UndoReset::Restored { reset_count, week_count }) = result {
+            if week_count > reset_count {
+                self.hooks.dispatch(IncrementOutcome {
+                    previous_count: reset_count,
+                    week_count,
+                    time: now,
+                    ip: None,
+                });
+            }
+        }
+        result
+    }
+
+    /// 异步刷新数据库到磁盘
+    #[allow(dead_code)]
+    pub fn flush_async(&self) -> JoinHandle<Result<(), DbError>> {
+        let week_tree = self.week_tree.clone();
+        tokio::task::spawn_blocking(move || {
             week_tree.flush()?;
             Ok(())
         })
     }
 }
 
-// 计算从预设时间开始的日期
-pub fn calculate_date_from_weeks(weeks: u64) -> DateTime<Utc> {
-    // 预设的起始时间：2024-01-01 00:00:00 UTC
-    let base_time = DateTime::<Utc>::from_timestamp(1704067200, 0).unwrap();
+// 预设的起始时间：2024-01-01 00:00:00 UTC，周数从这里开始计算
+pub fn base_time() -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(1704067200, 0).unwrap()
+}
 
+// 计算从预设时间开始的日期
+// 周数过大导致超出 chrono 可表示的范围时返回 DbError::DateOutOfRange，而不是 panic
+pub fn calculate_date_from_weeks(weeks: u64) -> Result<DateTime<Utc>, DbError> {
     // 计算目标时间（处理溢出）
-    let weeks_duration = Duration::weeks(weeks as i64);
-    base_time + weeks_duration
+    i64::try_from(weeks)
+        .ok()
+        .and_then(Duration::try_weeks)
+        .and_then(|weeks_duration| base_time().checked_add_signed(weeks_duration))
+        .ok_or(DbError::DateOutOfRange(weeks))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
+    use chrono::TimeZone;
 
     #[test]
     fn test_calculate_date() {
-        let date = calculate_date_from_weeks(0);
+        let date = calculate_date_from_weeks(0).unwrap();
         assert_eq!(date.timestamp(), 1704067200); // 2024-01-01 00:00:00 UTC
     }
 
     #[test]
     fn test_calculate_date_one_week() {
-        let date = calculate_date_from_weeks(1);
+        let date = calculate_date_from_weeks(1).unwrap();
         assert_eq!(date.timestamp(), 1704067200 + 7 * 24 * 60 * 60);
     }
+
+    #[test]
+    fn test_calculate_date_out_of_range() {
+        assert!(matches!(
+            calculate_date_from_weeks(u64::MAX),
+            Err(DbError::DateOutOfRange(u64::MAX))
+        ));
+        // 未超出 i64 但超出 chrono 可表示范围
+        assert!(matches!(
+            calculate_date_from_weeks(i64::MAX as u64 / 1_000_000),
+            Err(DbError::DateOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_data_dir() {
+        let dir = std::env::temp_dir().join(format!("teacon-probe-{}", std::process::id()));
+        assert!(check_data_dir(&dir.join("db")).is_ok());
+        assert!(!dir.join("db").join(".write_probe").exists());
+
+        // 父路径是文件时无法创建目录，应返回明确的错误
+        let file = dir.join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert!(matches!(
+            check_data_dir(&file.join("db")),
+            Err(DbError::DataDirNotWritable { .. })
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_opens_baseline_layout() {
+        // 最初版本只写入 weeks/current_week（bincode 编码的 WeekData）和 clicks/ip:<地址>（RFC3339 时间）
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let weeks = db.open_tree("weeks").unwrap();
+        let data = WeekData {
+            week_count: 42,
+            last_click_time: Some(Utc::now()),
+        };
+        weeks.insert(b"current_week", bincode::serialize(&data).unwrap()).unwrap();
+        let clicks = db.open_tree("clicks").unwrap();
+        clicks.insert(b"ip:1.2.3.4", Utc::now().to_rfc3339().as_bytes()).unwrap();
+
+        let db = Database::open(db, 0).unwrap();
+        assert_eq!(db.get_week_count().await.unwrap(), 42);
+        assert!(db.check_ip_window("1.2.3.4").await.unwrap());
+
+        // 当天已经访问过的 IP 不再计数，其他 IP 在原有周数上继续增加
+        assert!(!db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap());
+        assert!(db.increment_week_with_ip_check("5.6.7.8".to_string(), None, None).await.unwrap());
+        assert_eq!(db.get_week_count().await.unwrap(), 43);
+        assert_eq!(db.increment_week(None).await.unwrap(), 44);
+    }
+
+    #[tokio::test]
+    async fn test_delta_since() {
+        let db = Database::temporary().unwrap();
+        let before = Utc::now() - Duration::seconds(1);
+
+        db.increment_week(None).await.unwrap();
+        let middle = Utc::now();
+        db.increment_week(None).await.unwrap();
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+
+        // 早于所有记录时基准为 0
+        let delta = db.delta_since(before).await.unwrap();
+        assert_eq!(delta.increments, 3);
+        assert_eq!(delta.count_then, 0);
+        assert_eq!(delta.count_now, 3);
+
+        let delta = db.delta_since(middle).await.unwrap();
+        assert_eq!(delta.increments, 2);
+        assert_eq!(delta.weeks_added, 2.0);
+        assert_eq!(delta.count_then, 1);
+        assert_eq!(delta.count_now, 3);
+    }
+
+    #[tokio::test]
+    async fn test_milestones_follow_jumps_and_resets() {
+        use crate::milestones::Milestones;
+        use crate::sse::MilestoneBroadcaster;
+
+        let broadcaster = MilestoneBroadcaster::new(Milestones::new(vec![2, 5]));
+        let mut milestones = broadcaster.subscribe();
+        let mut hooks = HookRegistry::new();
+        hooks.register(broadcaster);
+        let db = Database::temporary().unwrap().with_hooks(hooks);
+
+        db.increment_week(None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        assert_eq!(milestones.recv().await.unwrap(), 2);
+
+        // 修正一次跨过的里程碑同样推送
+        db.adjust_week(3, "补记").await.unwrap();
+        assert_eq!(milestones.recv().await.unwrap(), 5);
+
+        // 重置后再次到达的里程碑再次推送
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        assert_eq!(milestones.recv().await.unwrap(), 2);
+
+        // 撤销重置恢复周数，跨过的里程碑也会推送
+        db.undo_reset(Duration::minutes(5)).await.unwrap();
+        assert_eq!(milestones.recv().await.unwrap(), 5);
+        assert!(milestones.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delta_since_follows_adjust_and_reset() {
+        let at = |minute| Utc.with_ymd_and_hms(2024, 3, 1, 8, minute, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(at(0)));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+
+        db.increment_week(None).await.unwrap();
+        db.increment_weighted(0.5, None).await.unwrap();
+        clock.set(at(10));
+        db.adjust_week(10, "补记").await.unwrap();
+        clock.set(at(20));
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        clock.set(at(30));
+        db.increment_week(None).await.unwrap();
+
+        // 修正之前：当时的周数来自最后一条历史记录
+        let delta = db.delta_since(at(5)).await.unwrap();
+        assert_eq!(delta.count_then, 2);
+        assert_eq!(delta.increments, 1);
+        assert_eq!(delta.count_now, 1);
+
+        // 修正之后、重置之前
+        let delta = db.delta_since(at(15)).await.unwrap();
+        assert_eq!(delta.count_then, 12);
+
+        // 重置之后
+        let delta = db.delta_since(at(25)).await.unwrap();
+        assert_eq!(delta.count_then, 0);
+
+        // 权重按实际增加的周数累计
+        let delta = db.delta_since(at(0)).await.unwrap();
+        assert_eq!(delta.increments, 3);
+        assert_eq!(delta.weeks_added, 2.5);
+    }
+
+    #[tokio::test]
+    async fn test_delta_since_follows_deleted_history() {
+        let at = |minute| Utc.with_ymd_and_hms(2024, 3, 1, 8, minute, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(at(0)));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+
+        db.increment_week(None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        let entries = db.list_history(1, None).await.unwrap();
+        let key = parse_history_key(&entries[0].0).unwrap();
+        clock.set(at(10));
+        db.delete_history(key, true).await.unwrap();
+
+        assert_eq!(db.delta_since(at(5)).await.unwrap().count_then, 2);
+        assert_eq!(db.delta_since(at(15)).await.unwrap().count_then, 1);
+
+        // 不减一的删除不改变周数
+        let entries = db.list_history(1, None).await.unwrap();
+        let key = parse_history_key(&entries[0].0).unwrap();
+        clock.set(at(20));
+        db.delete_history(key, false).await.unwrap();
+        assert_eq!(db.delta_since(at(5)).await.unwrap().count_then, 2);
+        assert_eq!(db.delta_since(at(25)).await.unwrap().count_then, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ip_increment_count() {
+        let db = Database::temporary().unwrap();
+        assert_eq!(db.ip_increment_count("1.2.3.4").await.unwrap(), 0);
+
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        // 同一天内重复访问不计数
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        // 按钮点击不计入任何 IP
+        db.increment_week(None).await.unwrap();
+
+        assert_eq!(db.ip_increment_count("1.2.3.4").await.unwrap(), 1);
+        assert_eq!(db.ip_increment_count("5.6.7.8").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_check_ip_window() {
+        let db = Database::temporary().unwrap();
+        assert!(!db.check_ip_window("1.2.3.4").await.unwrap());
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        assert!(db.check_ip_window("1.2.3.4").await.unwrap());
+        assert!(!db.check_ip_window("5.6.7.8").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_ip_info_user_agent() {
+        let db = Database::temporary().unwrap();
+        let long_agent = "浏览器".repeat(100);
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), Some(long_agent.clone()), None)
+            .await
+            .unwrap();
+        db.increment_week_with_ip_check("5.6.7.8".to_string(), None, None).await.unwrap();
+
+        let info = db.ip_info("1.2.3.4").await.unwrap();
+        assert_eq!(info.count, 1);
+        assert!(info.last_click_time.is_some());
+        let user_agent = info.user_agent.unwrap();
+        assert!(user_agent.len() <= MAX_USER_AGENT_LEN);
+        assert!(long_agent.starts_with(&user_agent));
+
+        // 未启用跟踪时不保存 User-Agent
+        assert_eq!(db.ip_info("5.6.7.8").await.unwrap().user_agent, None);
+
+        let unknown = db.ip_info("9.9.9.9").await.unwrap();
+        assert_eq!(unknown.count, 0);
+        assert!(unknown.last_click_time.is_none());
+    }
+
+    #[test]
+    fn test_history_key_roundtrip() {
+        let key = history_key(Utc::now(), 42);
+        let text = format_history_key(&key);
+        assert_eq!(text.len(), 32);
+        assert_eq!(parse_history_key(&text), Some(key));
+        assert_eq!(parse_history_key("zz"), None);
+        assert_eq!(parse_history_key(&"g".repeat(32)), None);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delete_history() {
+        let db = Database::temporary().unwrap();
+        db.increment_week(None).await.unwrap();
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+
+        // 按时间倒序
+        let entries = db.list_history(10, None).await.unwrap();
+        let counts: Vec<u64> = entries.iter().map(|(_, e)| e.week_count).collect();
+        assert_eq!(counts, vec![3, 2, 1]);
+
+        // 翻页
+        let before = parse_history_key(&entries[0].0).unwrap();
+        let page = db.list_history(1, Some(before)).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].1.week_count, 2);
+
+        // 删除但不减少周数
+        let key = parse_history_key(&entries[0].0).unwrap();
+        let (removed, week_count) = db.delete_history(key, false).await.unwrap().unwrap();
+        assert_eq!(removed.week_count, 3);
+        assert_eq!(week_count, 3);
+        assert!(db.delete_history(key, false).await.unwrap().is_none());
+
+        // 删除 IP 访问记录并同步减少周数和该 IP 的计数
+        let key = parse_history_key(&entries[1].0).unwrap();
+        let (removed, week_count) = db.delete_history(key, true).await.unwrap().unwrap();
+        assert_eq!(removed.ip.as_deref(), Some("1.2.3.4"));
+        assert_eq!(week_count, 2);
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+        assert_eq!(db.ip_increment_count("1.2.3.4").await.unwrap(), 0);
+        assert_eq!(db.list_history(10, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_derived() {
+        let db = Database::temporary().unwrap();
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        db.increment_week_with_ip_check("5.6.7.8".to_string(), None, None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+
+        // 模拟统计树在数据之后才加入：清空并写入无效数据
+        db.ip_count_tree.clear().unwrap();
+        db.ip_count_tree.insert("9.9.9.9", &5u64.to_be_bytes()).unwrap();
+        db.daily_tree.clear().unwrap();
+
+        db.rebuild_derived().await.unwrap();
+
+        assert_eq!(db.ip_increment_count("1.2.3.4").await.unwrap(), 1);
+        assert_eq!(db.ip_increment_count("5.6.7.8").await.unwrap(), 1);
+        assert_eq!(db.ip_increment_count("9.9.9.9").await.unwrap(), 0);
+        assert_eq!(db.growth_rate(1).await.unwrap(), 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_adjust_week() {
+        let db = Database::temporary().unwrap();
+        for _ in 0..5 {
+            db.increment_week(None).await.unwrap();
+        }
+
+        let adjustment = db.adjust_week(-3, "清理刷量").await.unwrap();
+        assert_eq!(adjustment.previous_count, 5);
+        assert_eq!(adjustment.week_count, 2);
+        assert_eq!(adjustment.applied, -3);
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+
+        // 不会低于 0
+        let adjustment = db.adjust_week(-10, "再次修正").await.unwrap();
+        assert_eq!(adjustment.week_count, 0);
+        assert_eq!(adjustment.applied, -2);
+
+        let adjustment = db.adjust_week(4, "补录").await.unwrap();
+        assert_eq!(adjustment.week_count, 4);
+
+        // 每次修正都留下档案记录
+        let archive = db.list_archive().await.unwrap();
+        assert_eq!(archive.len(), 3);
+        match &archive[1].action {
+            ArchiveAction::Adjust { delta, applied, reason, .. } => {
+                assert_eq!((*delta, *applied), (-10, -2));
+                assert_eq!(reason, "再次修正");
+            }
+            other => panic!("unexpected archive action: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_raw() {
+        let db = Database::temporary().unwrap();
+        db.increment_week(None).await.unwrap();
+
+        let raw = db.get_raw("week", "current_week").await.unwrap().unwrap();
+        let data: WeekData = bincode::deserialize(&raw).unwrap();
+        assert_eq!(data.week_count, 1);
+
+        assert!(db.get_raw("weeks", "missing").await.unwrap().is_none());
+        assert!(matches!(
+            db.get_raw("nope", "current_week").await,
+            Err(DbError::UnknownTree(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stale_count_fallback() {
+        let db = Database::temporary().unwrap();
+        db.week_tree.insert(b"current_week", &b"x"[..]).unwrap();
+
+        // 没有已知值时仍然报错
+        assert!(db.get_week_count_or_stale().await.is_err());
+
+        db.week_tree.remove(b"current_week").unwrap();
+        db.increment_week(None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        assert_eq!(db.get_week_count_or_stale().await.unwrap(), (2, false));
+
+        // 缓存失效后读取失败，退回最后已知的周数
+        db.week_tree.insert(b"current_week", &b"x"[..]).unwrap();
+        db.count_cache.invalidate();
+        assert_eq!(db.get_week_count_or_stale().await.unwrap(), (2, true));
+    }
+
+    #[tokio::test]
+    async fn test_increment_week_idempotent() {
+        let db = Database::temporary().unwrap();
+        let ttl = Duration::hours(1);
+
+        assert_eq!(db.increment_week_idempotent("a".to_string(), ttl, 1.0, None).await.unwrap(), (1, false));
+        // 同一个键重放首次的结果，不再增加
+        assert_eq!(db.increment_week_idempotent("a".to_string(), ttl, 1.0, None).await.unwrap(), (1, true));
+        assert_eq!(db.increment_week_idempotent("b".to_string(), ttl, 1.0, None).await.unwrap(), (2, false));
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+        assert_eq!(db.idempotent_replay("a", ttl).await.unwrap(), Some(1));
+        assert_eq!(db.idempotent_replay("c", ttl).await.unwrap(), None);
+
+        // 过期的键重新计数
+        assert_eq!(db.idempotent_replay("a", Duration::zero()).await.unwrap(), None);
+        let expired = db.increment_week_idempotent("a".to_string(), Duration::zero(), 1.0, None).await;
+        assert_eq!(expired.unwrap(), (3, false));
+    }
+
+    #[tokio::test]
+    async fn test_prune_idempotency_keys() {
+        let db = Database::temporary().unwrap();
+        let ttl = Duration::hours(1);
+        db.increment_week_idempotent("a".to_string(), ttl, 1.0, None).await.unwrap();
+        db.increment_week_idempotent("b".to_string(), ttl, 1.0, None).await.unwrap();
+
+        let past = Utc::now() - Duration::hours(1);
+        assert_eq!(db.prune_idempotency_keys(past).await.unwrap(), 0);
+
+        let future = Utc::now() + Duration::seconds(1);
+        assert_eq!(db.prune_idempotency_keys(future).await.unwrap(), 2);
+        assert!(db.idempotency_tree.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ip_dedupe_across_day_boundary() {
+        let at = |day: u32, hour: u32, minute: u32| {
+            Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap()
+        };
+        let clock = Arc::new(ManualClock::new(at(1, 23, 0)));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+        let ip = "10.0.0.1".to_string();
+
+        assert!(db.increment_week_with_ip_check(ip.clone(), None, None).await.unwrap());
+
+        // 同一个 UTC 日内不再计数
+        clock.set(at(1, 23, 30));
+        assert!(!db.increment_week_with_ip_check(ip.clone(), None, None).await.unwrap());
+
+        // 过了 UTC 零点即重新计数（按自然日而不是滑动的 24 小时窗口）
+        clock.set(at(2, 0, 30));
+        assert!(db.increment_week_with_ip_check(ip.clone(), None, None).await.unwrap());
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_take_period_count_loses_no_increments() {
+        let db = Arc::new(Database::temporary().unwrap());
+        db.increment_week(None).await.unwrap();
+        assert_eq!(db.take_period_count().await.unwrap(), 1);
+        assert_eq!(db.take_period_count().await.unwrap(), 0);
+
+        // 增加与读取清零并发进行
+        let increments: Vec<_> = (0..40)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { db.increment_week(None).await.unwrap() })
+            })
+            .collect();
+        let takes: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { db.take_period_count().await.unwrap() })
+            })
+            .collect();
+
+        for handle in increments {
+            handle.await.unwrap();
+        }
+        let mut taken = 0;
+        for handle in takes {
+            taken += handle.await.unwrap();
+        }
+        taken += db.take_period_count().await.unwrap();
+
+        assert_eq!(taken, 40);
+        // 总周数不受影响
+        assert_eq!(db.get_week_count().await.unwrap(), 41);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_roundtrip() {
+        let source = Database::temporary().unwrap();
+        source.increment_week(None).await.unwrap();
+        source
+            .increment_week_with_ip_check("10.0.0.1".to_string(), None, None)
+            .await
+            .unwrap();
+        let snapshot = source.export_snapshot().await.unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.trees.len(), SNAPSHOT_TREES.len());
+
+        let target = Database::temporary().unwrap();
+        for _ in 0..5 {
+            target.increment_week(None).await.unwrap();
+        }
+        target.get_week_count().await.unwrap();
+        target.import_snapshot(snapshot.clone()).await.unwrap();
+
+        // 周数和派生统计都被完整恢复
+        assert_eq!(target.get_week_count().await.unwrap(), 2);
+        assert_eq!(target.ip_increment_count("10.0.0.1").await.unwrap(), 1);
+        assert_eq!(target.list_history(10, None).await.unwrap().len(), 2);
+        assert!(target.check_ip_window("10.0.0.1").await.unwrap());
+
+        let mut wrong_version = snapshot.clone();
+        wrong_version.version = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            target.import_snapshot(wrong_version).await,
+            Err(DbError::InvalidSnapshot(_))
+        ));
+
+        let mut unknown_tree = snapshot;
+        unknown_tree.trees.insert("nope".to_string(), Vec::new());
+        assert!(matches!(
+            target.import_snapshot(unknown_tree).await,
+            Err(DbError::UnknownTree(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_blocking_ops_wait_for_permit() {
+        let db = Arc::new(Database::temporary().unwrap().with_max_blocking_ops(1));
+        let permit = db.blocking_permits.clone().acquire_owned().await.unwrap();
+
+        assert_eq!(db.blocking_stats().in_flight, 1);
+        assert_eq!(db.blocking_stats().available_permits, 0);
+
+        // 许可被占用时操作排队等待，而不是进入阻塞线程池
+        let pending = tokio::spawn({
+            let db = db.clone();
+            async move { db.increment_week(None).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!pending.is_finished());
+
+        drop(permit);
+        assert_eq!(pending.await.unwrap().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_source_breakdown() {
+        let db = Database::temporary().unwrap();
+        db.increment_week(Some("button")).await.unwrap();
+        db.increment_week(Some("button")).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, Some("qr"))
+            .await
+            .unwrap();
+
+        let breakdown = db.get_source_breakdown().await.unwrap();
+        assert_eq!(
+            breakdown,
+            vec![
+                ("button".to_string(), 2),
+                ("qr".to_string(), 1),
+                (DEFAULT_SOURCE.to_string(), 1),
+            ]
+        );
+
+        let (key, entry) = db.list_history(1, None).await.unwrap().remove(0);
+        assert_eq!(entry.source, "qr");
+
+        // 删除并扣减历史记录时来源计数同步减少，重建后保持一致
+        db.delete_history(parse_history_key(&key).unwrap(), true).await.unwrap();
+        db.rebuild_derived().await.unwrap();
+        let breakdown = db.get_source_breakdown().await.unwrap();
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[0], ("button".to_string(), 2));
+    }
+
+    #[tokio::test]
+    async fn test_slow_operation_times_out() {
+        let timeout = std::time::Duration::from_millis(50);
+        let db = Database::temporary().unwrap().with_op_timeout(timeout);
+
+        // 模拟卡住的存储操作
+        let slow = db.run_blocking(|| {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            Ok(())
+        });
+        assert!(matches!(slow.await, Err(DbError::Timeout(t)) if t == timeout));
+
+        // 正常的操作不受影响
+        assert_eq!(db.increment_week(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_operation_keeps_permit() {
+        let db = Database::temporary()
+            .unwrap()
+            .with_max_blocking_ops(1)
+            .with_op_timeout(std::time::Duration::from_millis(50));
+        let (release, released) = std::sync::mpsc::channel::<()>();
+
+        // 卡住的操作超时返回，但阻塞任务仍在运行
+        let stuck = db.run_blocking(move || {
+            released.recv().ok();
+            Ok(())
+        });
+        assert!(matches!(stuck.await, Err(DbError::Timeout(_))));
+        assert_eq!(db.blocking_stats().in_flight, 1);
+
+        // 许可仍被占用，新的操作排队直到超时
+        assert!(matches!(db.increment_week(None).await, Err(DbError::Timeout(_))));
+
+        // 阻塞操作结束后许可被释放
+        release.send(()).unwrap();
+        while db.blocking_stats().in_flight > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        assert_eq!(db.increment_week(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_growth_rate() {
+        let db = Database::temporary().unwrap();
+        assert_eq!(db.growth_rate(7).await.unwrap(), 0.0);
+
+        // 模拟 3 天前开始记录：3 天前 2 次，今天 3 次
+        let today = Utc::now().date_naive();
+        db.daily_tree
+            .insert(daily_key(today - Duration::days(3)), &2u64.to_be_bytes())
+            .unwrap();
+        for _ in 0..3 {
+            db.increment_week(None).await.unwrap();
+        }
+
+        // 只有 4 天的数据，按 4 天求平均
+        assert_eq!(db.growth_rate(7).await.unwrap(), 5.0 / 4.0);
+        // 窗口只覆盖今天
+        assert_eq!(db.growth_rate(1).await.unwrap(), 3.0);
+        assert_eq!(db.growth_rate(0).await.unwrap(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_count_cache() {
+        let db = Database::temporary().unwrap();
+        db.week_tree
+            .insert(b"current_week", bincode::serialize(&WeekData {
+                week_count: 10,
+                last_click_time: None,
+            }).unwrap())
+            .unwrap();
+
+        assert_eq!(db.warm_cache().await.unwrap(), 10);
+        assert_eq!(db.count_cache.get(), Some(10));
+
+        // 增加后缓存同步更新
+        assert_eq!(db.increment_week(None).await.unwrap(), 11);
+        assert_eq!(db.get_week_count().await.unwrap(), 11);
+
+        // 重置后缓存失效
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        assert_eq!(db.count_cache.get(), None);
+        assert_eq!(db.get_week_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reset_during_increment_does_not_restore_stale_count() {
+        let db = Database::temporary().unwrap();
+        for _ in 0..5 {
+            db.increment_week(None).await.unwrap();
+        }
+
+        // 一次增加在重置前开始，在重置使缓存失效后才写回缓存
+        let generation = db.count_cache.generation();
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        db.count_cache.advance(generation, 6);
+        assert_eq!(db.count_cache.get(), None);
+        assert_eq!(db.get_week_count().await.unwrap(), 0);
+
+        // 失效后开始的操作照常写入缓存
+        assert_eq!(db.increment_week(None).await.unwrap(), 1);
+        assert_eq!(db.count_cache.get(), Some(1));
+    }
+
+    #[test]
+    fn test_conflicting_transaction_gives_up() {
+        // sled 用全局锁串行化事务，线程之间无法稳定制造冲突
+        // 这里让事务体每次都报告冲突，走 sled 真实的冲突重试循环
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("weeks").unwrap();
+        let attempts = Cell::new(0);
+        let runs = Cell::new(0);
+
+        let result: Result<(), TransactionError<DbError>> = tree.transaction(|tx| {
+            check_attempts(&attempts, 3)?;
+            runs.set(runs.get() + 1);
+            tx.insert(b"current_week", b"stale")?;
+            Err(ConflictableTransactionError::Conflict)
+        });
+
+        // 冲突的事务体恰好运行上限次数，之后放弃
+        assert!(matches!(transaction_error(result.unwrap_err()), DbError::Contention(3)));
+        assert_eq!(runs.get(), 3);
+        // 放弃的事务不会留下任何修改
+        assert!(tree.get(b"current_week").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_transaction_within_attempt_limit_commits() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("weeks").unwrap();
+        let attempts = Cell::new(0);
+        let conflicts = Cell::new(0);
+
+        // 前两次冲突，第三次在上限内提交
+        let result: Result<(), TransactionError<DbError>> = tree.transaction(|tx| {
+            check_attempts(&attempts, 3)?;
+            tx.insert(b"current_week", b"fresh")?;
+            if conflicts.get() < 2 {
+                conflicts.set(conflicts.get() + 1);
+                return Err(ConflictableTransactionError::Conflict);
+            }
+            Ok(())
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(tree.get(b"current_week").unwrap().unwrap(), b"fresh");
+    }
+
+    #[tokio::test]
+    async fn test_past_freeze_rejects_increments() {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+        db.increment_week(None).await.unwrap();
+        let db = db.with_freeze_at(Some(Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()));
+
+        // 所有增加入口都返回冻结时的周数，不写入任何记录
+        assert_eq!(db.increment_week(None).await.unwrap(), 1);
+        assert_eq!(
+            db.increment_week_idempotent("key".to_string(), Duration::hours(1), 1.0, None).await.unwrap(),
+            (1, false)
+        );
+        assert!(!db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap());
+        assert_eq!(db.get_week_count().await.unwrap(), 1);
+        assert_eq!(db.list_history(10, None).await.unwrap().len(), 1);
+        assert!(!db.check_ip_window("1.2.3.4").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_future_freeze_allows_increments() {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()));
+        let db = Database::temporary()
+            .unwrap()
+            .with_clock(clock.clone())
+            .with_freeze_at(Some(Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap()));
+
+        assert_eq!(db.increment_week(None).await.unwrap(), 1);
+        assert!(db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap());
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+
+        // 到达冻结时间后不再增加
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap());
+        assert_eq!(db.increment_week(None).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_increments_by_hour() {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+        db.increment_week(None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 2, 23, 30, 0).unwrap());
+        db.increment_week(None).await.unwrap();
+
+        let utc = db.increments_by_hour(FixedOffset::east_opt(0).unwrap()).await.unwrap();
+        assert_eq!(utc[8], 2);
+        assert_eq!(utc[23], 1);
+        assert_eq!(utc.iter().sum::<u64>(), 3);
+
+        // UTC+8 下 23:30 落在次日 7 点
+        let local = db.increments_by_hour(FixedOffset::east_opt(8 * 3600).unwrap()).await.unwrap();
+        assert_eq!(local[16], 2);
+        assert_eq!(local[7], 1);
+        assert_eq!(local[23], 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_ip_check_counts_once() {
+        let db = Arc::new(Database::temporary().unwrap());
+        // 所有请求在同一时刻开始，尽量让它们同时通过事务外的快速检查
+        let barrier = Arc::new(tokio::sync::Barrier::new(32));
+        let tasks: Vec<_> = (0..32)
+            .map(|_| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap()
+                })
+            })
+            .collect();
+
+        let mut counted = 0;
+        for task in tasks {
+            if task.await.unwrap() {
+                counted += 1;
+            }
+        }
+        assert_eq!(counted, 1);
+        assert_eq!(db.get_week_count().await.unwrap(), 1);
+        assert_eq!(db.list_history(100, None).await.unwrap().len(), 1);
+        assert_eq!(db.ip_info("1.2.3.4").await.unwrap().count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_initial_count_only_applies_to_new_database() {
+        // 在同一个实例上模拟两次启动，避免关闭后 sled 文件锁延迟释放导致重新打开失败
+        let db = Database::temporary().unwrap();
+        db.apply_initial_count(100).unwrap();
+        assert_eq!(db.get_week_count().await.unwrap(), 100);
+        assert_eq!(db.increment_week(None).await.unwrap(), 101);
+
+        // 已有周数的数据库忽略初始值
+        db.apply_initial_count(5).unwrap();
+        db.count_cache.invalidate();
+        assert_eq!(db.get_week_count().await.unwrap(), 101);
+    }
+
+    /// 按 IP 前缀返回固定国家的查询来源
+    struct PrefixLookup;
+
+    impl CountryLookup for PrefixLookup {
+        fn country(&self, ip: &str) -> Option<String> {
+            match ip.split('.').next() {
+                Some("1") => Some("CN".to_string()),
+                Some("2") => Some("JP".to_string()),
+                _ => None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_country_breakdown() {
+        let db = Database::temporary().unwrap().with_geoip(Some(Arc::new(PrefixLookup)));
+        for ip in ["1.0.0.1", "1.0.0.2", "2.0.0.1", "9.9.9.9"] {
+            assert!(db.increment_week_with_ip_check(ip.to_string(), None, None).await.unwrap());
+        }
+        // 重复访问不计数
+        assert!(!db.increment_week_with_ip_check("1.0.0.1".to_string(), None, None).await.unwrap());
+
+        // 查询不到国家的 IP 照常计数
+        assert_eq!(db.get_week_count().await.unwrap(), 4);
+        assert_eq!(
+            db.get_country_breakdown().await.unwrap(),
+            vec![("CN".to_string(), 2), ("JP".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_country_breakdown_follows_history() {
+        let db = Database::temporary().unwrap().with_geoip(Some(Arc::new(PrefixLookup)));
+        for ip in ["1.0.0.1", "1.0.0.2", "2.0.0.1"] {
+            db.increment_week_with_ip_check(ip.to_string(), None, None).await.unwrap();
+        }
+
+        // 删除记录时同时扣除国家计数，减到 0 的国家不再出现
+        let history = db.list_history(10, None).await.unwrap();
+        assert_eq!(history[0].1.country.as_deref(), Some("JP"));
+        db.delete_history(parse_history_key(&history[0].0).unwrap(), true).await.unwrap();
+        assert_eq!(db.get_country_breakdown().await.unwrap(), vec![("CN".to_string(), 2)]);
+
+        // 重建时从历史记录恢复国家计数
+        db.country_tree.clear().unwrap();
+        db.country_tree.insert("US", &7u64.to_be_bytes()).unwrap();
+        db.rebuild_derived().await.unwrap();
+        assert_eq!(db.get_country_breakdown().await.unwrap(), vec![("CN".to_string(), 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_country_breakdown_empty_without_geoip() {
+        let db = Database::temporary().unwrap();
+        db.increment_week_with_ip_check("1.0.0.1".to_string(), None, None).await.unwrap();
+        assert!(db.get_country_breakdown().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_undo_reset_within_grace_period() {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+        for _ in 0..5 {
+            db.increment_week(None).await.unwrap();
+        }
+        let reset = db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        assert_eq!(reset.previous_count, 5);
+        assert_eq!(reset.undo_deadline, Utc.with_ymd_and_hms(2024, 3, 1, 8, 5, 0).unwrap());
+        assert_eq!(db.get_week_count().await.unwrap(), 0);
+
+        // 重置后新增的次数在撤销后保留
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 1, 8, 4, 0).unwrap());
+        db.increment_week(None).await.unwrap();
+        assert_eq!(
+            db.undo_reset(Duration::minutes(5)).await.unwrap(),
+            UndoReset::Restored { reset_count: 1, week_count: 6 }
+        );
+        assert_eq!(db.get_week_count().await.unwrap(), 6);
+
+        // 同一次重置只能撤销一次
+        assert_eq!(db.undo_reset(Duration::minutes(5)).await.unwrap(), UndoReset::NothingToUndo);
+        let archive = db.list_archive().await.unwrap();
+        assert!(matches!(
+            archive[0].action,
+            ArchiveAction::UndoReset { reset_count: 1, week_count: 6 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_undo_reset_after_grace_period_expires() {
+        let reset_at = Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(reset_at));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+        assert_eq!(db.undo_reset(Duration::minutes(5)).await.unwrap(), UndoReset::NothingToUndo);
+
+        db.increment_week(None).await.unwrap();
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 1, 8, 6, 0).unwrap());
+        assert_eq!(
+            db.undo_reset(Duration::minutes(5)).await.unwrap(),
+            UndoReset::Expired { reset_at }
+        );
+        assert_eq!(db.get_week_count().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_weight_rounding() {
+        assert_eq!(weight_units(0.5), 500);
+        assert_eq!(weight_units(0.3334), 333);
+        assert_eq!(weight_units(2.0), COUNT_UNIT);
+        assert_eq!(weight_units(-1.0), 0);
+        assert_eq!(weight_units(f64::NAN), 0);
+
+        assert_eq!(round_units(0), 0);
+        assert_eq!(round_units(499), 0);
+        assert_eq!(round_units(500), 1);
+        assert_eq!(round_units(1499), 1);
+        assert_eq!(round_units(2500), 3);
+    }
+
+    #[tokio::test]
+    async fn test_increment_weighted_rounds_displayed_count() {
+        let db = Database::temporary().unwrap();
+        db.increment_week(None).await.unwrap();
+
+        // 1.3 四舍五入为 1，1.6 进位为 2
+        assert_eq!(db.increment_weighted(0.3, None).await.unwrap(), 1);
+        assert_eq!(db.increment_weighted(0.3, None).await.unwrap(), 2);
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+        // 整周增加保留不足一周的部分：1.6 -> 2.6
+        assert_eq!(db.increment_week(None).await.unwrap(), 3);
+
+        // 权重为 0 时不记录
+        assert_eq!(db.increment_weighted(0.0, None).await.unwrap(), 3);
+        let history = db.list_history(10, None).await.unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[1].1.units, 300);
+
+        // 删除加权记录时按记录的权重扣除：2.6 -> 2.3
+        let key = parse_history_key(&history[1].0).unwrap();
+        db.delete_history(key, true).await.unwrap();
+        assert_eq!(db.get_week_count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_adjust_and_reset() {
+        let db = Database::temporary().unwrap();
+        db.increment_weighted(0.5, None).await.unwrap();
+        assert_eq!(db.get_week_count().await.unwrap(), 1);
+
+        // 修正按整周调整精确总数：0.5 + 2 = 2.5 -> 3，再减 3 = 0
+        assert_eq!(db.adjust_week(2, "补录").await.unwrap().week_count, 3);
+        assert_eq!(db.adjust_week(-3, "清理").await.unwrap().week_count, 0);
+
+        // 重置清除不足一周的部分
+        db.increment_weighted(0.4, None).await.unwrap();
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        assert_eq!(db.increment_weighted(0.4, None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_record_button_click_ignores_page_views() {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+
+        // 首页访问不算作按钮点击
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        assert!(!db.record_button_click("1.2.3.4").await.unwrap());
+        assert!(db.record_button_click("1.2.3.4").await.unwrap());
+        assert!(!db.record_button_click("5.6.7.8").await.unwrap());
+
+        // 第二天重新计算
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap());
+        assert!(!db.record_button_click("1.2.3.4").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_prune_button_clicks() {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()));
+        let db = Database::temporary().unwrap().with_clock(clock.clone());
+        db.record_button_click("1.2.3.4").await.unwrap();
+        assert_eq!(db.prune_button_clicks().await.unwrap(), 0);
+
+        // 第二天清理前一天的记录，当天的记录保留
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap());
+        db.record_button_click("5.6.7.8").await.unwrap();
+        assert_eq!(db.prune_button_clicks().await.unwrap(), 1);
+        assert!(db.button_click_tree.get("1.2.3.4").unwrap().is_none());
+        assert!(db.record_button_click("5.6.7.8").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_increments_total_never_decreases() {
+        let db = Database::temporary().unwrap();
+        let snapshot = db.export_snapshot().await.unwrap();
+        db.increment_week(None).await.unwrap();
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        assert_eq!(db.metrics_snapshot().await.unwrap().increments_total, 3);
+
+        // 删除历史记录和重置不影响累计次数
+        let (key, _) = &db.list_history(1, None).await.unwrap()[0];
+        db.delete_history(parse_history_key(key).unwrap(), true).await.unwrap();
+        db.reset_weeks(Duration::minutes(5)).await.unwrap();
+        assert_eq!(db.metrics_snapshot().await.unwrap().increments_total, 3);
+
+        // 导入较早的快照也不会变小
+        db.import_snapshot(snapshot).await.unwrap();
+        assert_eq!(db.metrics_snapshot().await.unwrap().increments_total, 3);
+        assert_eq!(db.increment_week(None).await.unwrap(), 1);
+        assert_eq!(db.metrics_snapshot().await.unwrap().increments_total, 4);
+    }
+
+    #[tokio::test]
+    async fn test_seed_increments_total_from_history() {
+        let db = Database::temporary().unwrap();
+        db.increment_week(None).await.unwrap();
+        db.increment_week(None).await.unwrap();
+        // 模拟计数器加入之前创建的数据库
+        db.week_tree.remove(INCREMENTS_TOTAL_KEY).unwrap();
+
+        db.seed_increments_total().unwrap();
+        assert_eq!(db.metrics_snapshot().await.unwrap().increments_total, 2);
+        // 已有计数器时不覆盖
+        db.increment_week(None).await.unwrap();
+        db.seed_increments_total().unwrap();
+        assert_eq!(db.metrics_snapshot().await.unwrap().increments_total, 3);
+    }
 }
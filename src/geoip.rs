@@ -0,0 +1,47 @@
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+/// IP 所属国家的查询来源，数据库通过它为首页访问归属国家，测试中可替换为固定结果
+pub trait CountryLookup: Send + Sync {
+    /// 返回 IP 所属国家的 ISO 3166-1 代码，无法确定时返回 None
+    fn country(&self, ip: &str) -> Option<String>;
+}
+
+/// 基于 MaxMind mmdb 文件（GeoLite2-Country 等）的国家查询
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    /// 将 mmdb 文件整个读入内存
+    pub fn open(path: &str) -> Result<Self, MaxMindDBError> {
+        Ok(GeoIp {
+            reader: Reader::open_readfile(path)?,
+        })
+    }
+}
+
+impl CountryLookup for GeoIp {
+    fn country(&self, ip: &str) -> Option<String> {
+        // 查询失败（无效 IP、内网地址等）时不归属国家，不影响计数
+        let address: IpAddr = ip.parse().ok()?;
+        match self.reader.lookup::<geoip2::Country>(address) {
+            Ok(record) => record.country?.iso_code.map(|code| code.to_string()),
+            Err(e) => {
+                log::debug!("查询 IP {} 的国家失败: {}", ip, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_missing_file_fails() {
+        assert!(GeoIp::open("/nonexistent/GeoLite2-Country.mmdb").is_err());
+    }
+}
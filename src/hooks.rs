@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::config::AppConfig;
+use crate::db::IncrementOutcome;
+
+/// 每个钩子等待处理的增加通知上限，队列满时丢弃新的通知
+pub const HOOK_QUEUE_CAPACITY: usize = 1024;
+
+/// 增加周数钩子，每次成功增加后调用
+/// 用于执行自定义副作用（webhook、指标、外部日志等），无需修改处理函数
+#[async_trait]
+pub trait IncrementHook: Send + Sync {
+    /// 钩子名称，用于日志
+    fn name(&self) -> &str;
+
+    /// 成功增加周数后调用
+    async fn on_increment(&self, outcome: &IncrementOutcome);
+}
+
+/// 钩子注册表
+/// 每个钩子由各自的后台任务按顺序处理，通知经有界队列传递，慢钩子不会拖慢其他钩子或无限堆积任务
+#[derive(Clone, Default)]
+pub struct HookRegistry {
+    hooks: Vec<Arc<dyn IncrementHook>>,
+    /// 每个钩子的通知队列，首次分发时启动对应的后台任务
+    queues: Arc<OnceLock<Vec<mpsc::Sender<IncrementOutcome>>>>,
+}
+
+impl HookRegistry {
+    /// 创建空的注册表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 根据配置创建注册表并注册内置钩子
+    pub fn from_config(config: &AppConfig) -> Self {
+        let mut registry = Self::new();
+        for name in &config.increment_hooks {
+            match name.as_str() {
+                "log" => registry.register(LoggingHook),
+                "webhook" => match &config.webhook_url {
+                    Some(url) => registry.register(WebhookHook::new(url.clone(), config.webhook_include_ip)),
+                    None => log::warn!("已启用 webhook 钩子但未设置 WEBHOOK_URL，跳过"),
+                },
+                other => log::warn!("未知的增加周数钩子: {}", other),
+            }
+        }
+        registry
+    }
+
+    /// 注册一个钩子
+    pub fn register(&mut self, hook: impl IncrementHook + 'static) {
+        log::info!("注册增加周数钩子: {}", hook.name());
+        self.hooks.push(Arc::new(hook));
+        // 钩子列表变化后重新启动后台任务，不与注册前的副本共用队列
+        self.queues = Arc::default();
+    }
+
+    /// 将通知放入每个钩子的队列，由后台任务调用钩子，不阻塞调用方
+    /// 返回成功入队的钩子数；某个钩子的队列已满时丢弃给它的这次通知并记录警告
+    pub fn dispatch(&self, outcome: IncrementOutcome) -> usize {
+        if self.hooks.is_empty() {
+            return 0;
+        }
+
+        let queues = self.queues.get_or_init(|| self.hooks.iter().cloned().map(spawn_worker).collect());
+        let mut queued = 0;
+        for (hook, queue) in self.hooks.iter().zip(queues) {
+            match queue.try_send(outcome.clone()) {
+                Ok(()) => queued += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    log::warn!("钩子 {} 的队列已满，丢弃周数 {} 的通知", hook.name(), outcome.week_count);
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    log::warn!("钩子 {} 的后台任务已退出", hook.name());
+                }
+            }
+        }
+        queued
+    }
+}
+
+/// 启动处理单个钩子的后台任务，返回它的通知队列；注册表的所有副本释放后任务结束
+fn spawn_worker(hook: Arc<dyn IncrementHook>) -> mpsc::Sender<IncrementOutcome> {
+    let (sender, mut receiver) = mpsc::channel(HOOK_QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(outcome) = receiver.recv().await {
+            hook.on_increment(&outcome).await;
+        }
+    });
+    sender
+}
+
+/// 内置钩子：将每次增加记录到日志
+pub struct LoggingHook;
+
+#[async_trait]
+impl IncrementHook for LoggingHook {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    async fn on_increment(&self, outcome: &IncrementOutcome) {
+        log::info!(
+            "[钩子] 周数增加到 {}，时间: {}，IP: {}",
+            outcome.week_count,
+            outcome.time.to_rfc3339(),
+            outcome.ip.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/// webhook 请求体，默认不包含客户端 IP
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    week_count: u64,
+    time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<&'a str>,
+}
+
+/// 内置钩子：将每次增加以 JSON 形式 POST 到指定地址
+pub struct WebhookHook {
+    client: reqwest::Client,
+    url: String,
+    /// 是否在请求体中附带客户端 IP（`WEBHOOK_INCLUDE_IP`）
+    include_ip: bool,
+}
+
+impl WebhookHook {
+    /// webhook 请求超时时间
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(url: String, include_ip: bool) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Self::TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        WebhookHook { client, url, include_ip }
+    }
+
+    /// 构建请求体，未开启 `include_ip` 时不发送 IP
+    fn payload<'a>(&self, outcome: &'a IncrementOutcome) -> WebhookPayload<'a> {
+        WebhookPayload {
+            week_count: outcome.week_count,
+            time: outcome.time,
+            ip: outcome.ip.as_deref().filter(|_| self.include_ip),
+        }
+    }
+}
+
+#[async_trait]
+impl IncrementHook for WebhookHook {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn on_increment(&self, outcome: &IncrementOutcome) {
+        // 失败只记录日志，不影响计数
+        match self.client.post(&self.url).json(&self.payload(outcome)).send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                log::warn!("webhook 返回非成功状态码: {}", response.status());
+            }
+            Err(e) => {
+                log::warn!("发送 webhook 失败: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Notify;
+
+    /// 将收到的周数转发到通道
+    struct RecordingHook(mpsc::UnboundedSender<u64>);
+
+    #[async_trait]
+    impl IncrementHook for RecordingHook {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn on_increment(&self, outcome: &IncrementOutcome) {
+            self.0.send(outcome.week_count).ok();
+        }
+    }
+
+    /// 一直卡住、直到被通知才返回的钩子
+    struct StuckHook(Arc<Notify>);
+
+    #[async_trait]
+    impl IncrementHook for StuckHook {
+        fn name(&self) -> &str {
+            "stuck"
+        }
+
+        async fn on_increment(&self, _outcome: &IncrementOutcome) {
+            self.0.notified().await;
+        }
+    }
+
+    fn outcome(week_count: u64) -> IncrementOutcome {
+        IncrementOutcome {
+            previous_count: week_count.saturating_sub(1),
+            week_count,
+            time: Utc::now(),
+            ip: Some("1.2.3.4".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_runs_hooks() {
+        let (sender, mut seen) = mpsc::unbounded_channel();
+        let mut registry = HookRegistry::new();
+        registry.register(RecordingHook(sender));
+
+        assert_eq!(registry.dispatch(outcome(7)), 1);
+        assert_eq!(registry.dispatch(outcome(8)), 1);
+        // 同一个钩子按顺序收到通知
+        assert_eq!(seen.recv().await, Some(7));
+        assert_eq!(seen.recv().await, Some(8));
+    }
+
+    #[test]
+    fn test_empty_registry_does_not_spawn() {
+        assert_eq!(HookRegistry::new().dispatch(outcome(1)), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_hook_queue_is_bounded() {
+        let (sender, mut seen) = mpsc::unbounded_channel();
+        let mut registry = HookRegistry::new();
+        registry.register(StuckHook(Arc::new(Notify::new())));
+        registry.register(RecordingHook(sender));
+
+        // 卡住的钩子正在处理第一条通知，之后最多积压队列上限条
+        // 其他钩子照常处理每一条通知
+        let accepted = HOOK_QUEUE_CAPACITY as u64 + 1;
+        for week_count in 0..accepted {
+            assert_eq!(registry.dispatch(outcome(week_count)), 2);
+            assert_eq!(seen.recv().await, Some(week_count));
+        }
+
+        // 队列满后只丢弃给卡住的钩子的那份
+        assert_eq!(registry.dispatch(outcome(accepted)), 1);
+        assert_eq!(seen.recv().await, Some(accepted));
+    }
+
+    #[test]
+    fn test_webhook_payload_omits_ip_by_default() {
+        let outcome = outcome(3);
+        let hook = WebhookHook::new("http://127.0.0.1:9/hook".to_string(), false);
+        let body = serde_json::to_value(hook.payload(&outcome)).unwrap();
+        assert!(body.get("ip").is_none());
+        assert_eq!(body["week_count"], 3);
+
+        let hook = WebhookHook::new("http://127.0.0.1:9/hook".to_string(), true);
+        let body = serde_json::to_value(hook.payload(&outcome)).unwrap();
+        assert_eq!(body["ip"], "1.2.3.4");
+    }
+}
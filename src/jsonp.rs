@@ -0,0 +1,66 @@
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+
+/// 回调函数名的最大长度
+pub const MAX_CALLBACK_LEN: usize = 64;
+
+/// 检查回调函数名是否为安全的 JavaScript 标识符（允许 `a.b.c` 形式的成员访问）
+/// 拒绝其他任何字符，防止通过回调参数注入脚本
+pub fn is_valid_callback(callback: &str) -> bool {
+    if callback.is_empty() || callback.len() > MAX_CALLBACK_LEN {
+        return false;
+    }
+
+    callback.split('.').all(|part| {
+        let mut chars = part.chars();
+        match chars.next() {
+            Some(first) if first.is_ascii_alphabetic() || first == '_' || first == '$' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+            }
+            _ => false,
+        }
+    })
+}
+
+/// 输出 JSON 响应；提供回调函数名时包装为 JSONP
+/// 调用方需先用 `is_valid_callback` 校验回调函数名
+pub fn respond<T: Serialize>(
+    mut builder: HttpResponseBuilder,
+    body: &T,
+    callback: Option<&str>,
+) -> HttpResponse {
+    match callback {
+        Some(callback) => {
+            let json = serde_json::to_string(body).unwrap_or_else(|_| "null".to_string());
+            builder
+                .content_type("application/javascript; charset=utf-8")
+                .insert_header(("X-Content-Type-Options", "nosniff"))
+                // 开头的注释可以防御以 JSONP 响应伪装其他内容类型的攻击
+                .body(format!("/**/{}({});", callback, json))
+        }
+        None => builder.json(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_callbacks() {
+        assert!(is_valid_callback("handleData"));
+        assert!(is_valid_callback("$_cb1"));
+        assert!(is_valid_callback("widget.callbacks.onData"));
+    }
+
+    #[test]
+    fn test_invalid_callbacks() {
+        assert!(!is_valid_callback(""));
+        assert!(!is_valid_callback("1abc"));
+        assert!(!is_valid_callback("alert(1)//"));
+        assert!(!is_valid_callback("a..b"));
+        assert!(!is_valid_callback("a.b."));
+        assert!(!is_valid_callback("fn;alert"));
+        assert!(!is_valid_callback(&"a".repeat(MAX_CALLBACK_LEN + 1)));
+    }
+}
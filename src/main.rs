@@ -1,69 +1,295 @@
+mod admin;
+mod badge;
+mod clock;
+mod compression;
+mod config;
 mod db;
+mod geoip;
+mod hooks;
+mod jsonp;
+mod metrics;
+mod milestones;
+mod pushgateway;
+mod rate_limit;
+mod season;
+mod sse;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod template;
+mod trust;
 
-use actix_web::{web, App, HttpResponse, HttpServer, Responder, HttpRequest};
+use actix_web::{middleware, web, App, HttpResponse, HttpServer, Responder, HttpRequest};
 use actix_cors::Cors;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use db::Database;
+use config::{AppConfig, TrustedHops};
+use db::{Database, DbError, UndoReset, WeekDelta};
+use hooks::HookRegistry;
+use rate_limit::GlobalRateGate;
+use season::PreviousSeason;
+use sse::{EventBroadcaster, MilestoneBroadcaster};
 
 /// API 响应结构
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct ApiResponse {
     success: bool,
     week_count: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     message: Option<String>,
+    /// 周数为 0（尚未开始）时为 true，仅在启用 `MARK_INITIAL` 时返回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_initial: Option<bool>,
+    /// 数据库读取失败、返回的是最后已知的周数时为 true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stale: Option<bool>,
+    /// 本次请求是否增加了周数（`/api/data` 专用，前端据此播放动画）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    just_counted: Option<bool>,
+}
+
+impl ApiResponse {
+    /// 只带周数的成功响应
+    fn ok(week_count: u64) -> Self {
+        ApiResponse {
+            success: true,
+            week_count,
+            ..Default::default()
+        }
+    }
+
+    /// 失败响应，周数为 0
+    fn error(message: impl Into<String>) -> Self {
+        ApiResponse {
+            message: Some(message.into()),
+            ..Default::default()
+        }
+    }
+
+    /// 返回当前周数的成功响应，周数为 0 时按配置附带初始状态标记和提示
+    fn current(week_count: u64, config: &AppConfig) -> Self {
+        let initial = week_count == 0;
+        ApiResponse {
+            success: true,
+            week_count,
+            message: config.initial_message.clone().filter(|_| initial),
+            is_initial: (config.mark_initial && initial).then_some(true),
+            ..Default::default()
+        }
+    }
+
+    /// 标记周数是否为数据库出错时退回的最后已知值
+    fn stale(mut self, stale: bool) -> Self {
+        self.stale = stale.then_some(true);
+        self
+    }
+
+    /// 附带本次请求是否增加了周数
+    fn just_counted(mut self, counted: bool) -> Self {
+        self.just_counted = Some(counted);
+        self
+    }
+}
+
+/// 统计接口的计数单位：按增加事件计数，每次增加计 1，不考虑 `WEIGHTED_INCREMENTS` 的权重
+const STATS_UNIT: &str = "events";
+
+/// 周数变化 API 响应结构
+#[derive(Serialize)]
+struct DeltaResponse {
+    success: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    delta: Option<WeekDelta>,
+    /// `increments` 的计数单位
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// 获取数据查询参数
+#[derive(Deserialize)]
+struct DataQuery {
+    /// JSONP 回调函数名
+    callback: Option<String>,
+}
+
+/// 周数变化查询参数
+#[derive(Deserialize)]
+struct DeltaQuery {
+    since: String,
+}
+
+/// 增长速率查询参数
+#[derive(Deserialize)]
+struct RateQuery {
+    #[serde(default = "default_rate_days")]
+    days: u32,
+}
+
+fn default_rate_days() -> u32 {
+    7
+}
+
+/// 增长速率查询允许的最大天数
+const MAX_RATE_DAYS: u32 = 3650;
+
+/// 历史记录列表查询参数
+#[derive(Deserialize)]
+struct HistoryQuery {
+    #[serde(default = "default_history_limit")]
+    limit: usize,
+    /// 翻页游标：只返回该键之前的记录
+    before: Option<String>,
+}
+
+fn default_history_limit() -> usize {
+    100
+}
+
+/// 历史记录列表单次返回的最大条数
+const MAX_HISTORY_LIMIT: usize = 1000;
+
+/// 删除历史记录的查询参数
+#[derive(Deserialize)]
+struct DeleteHistoryQuery {
+    /// 是否同时将周数减一
+    #[serde(default)]
+    decrement: bool,
+}
+
+/// 从 X-Forwarded-For 中选出客户端 IP
+/// 按从右到左的语义，跳过末尾 `TRUSTED_HOPS` 个可信代理添加的条目，取下一个；
+/// `all` 表示信任所有代理，直接取第一个条目
+fn forwarded_client_ip(forwarded: &str, trusted_hops: TrustedHops) -> Option<&str> {
+    let mut entries = forwarded.split(',').map(|entry| entry.trim());
+    match trusted_hops {
+        TrustedHops::All => entries.next().filter(|ip| !ip.is_empty()),
+        // 空条目不是代理添加的，不计入层数；条目数不足时最左边的条目由客户端控制，不可信
+        TrustedHops::Count(hops) => entries.filter(|ip| !ip.is_empty()).rev().nth(hops),
+    }
+}
+
+/// 记录访问查询参数
+#[derive(Deserialize)]
+struct VisitQuery {
+    /// 来源标签（如二维码落地页传 `qr`），默认 `visit`
+    src: Option<String>,
+}
+
+/// 来源标签的最大长度
+const MAX_SOURCE_LEN: usize = 32;
+
+/// 检查来源标签是否合法：非空，只包含字母、数字、`-` 和 `_`
+fn is_valid_source(source: &str) -> bool {
+    !source.is_empty()
+        && source.len() <= MAX_SOURCE_LEN
+        && source.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 原始数据查询参数
+#[derive(Deserialize)]
+struct RawQuery {
+    tree: String,
+    key: String,
+}
+
+/// 周数修正请求体
+#[derive(Deserialize)]
+struct AdjustRequest {
+    delta: i64,
+    reason: String,
+}
+
+/// 修正原因的最大长度（字符数）
+const MAX_ADJUST_REASON_LEN: usize = 500;
+
+/// 根据数据库错误选择响应状态：超时和事务竞争是暂时的，返回可重试的 503，其他错误返回 500
+fn db_error_response(e: &DbError) -> actix_web::HttpResponseBuilder {
+    match e {
+        DbError::Timeout(_) | DbError::Contention(_) => {
+            let mut builder = HttpResponse::ServiceUnavailable();
+            builder.insert_header(("Retry-After", "1"));
+            builder
+        }
+        _ => HttpResponse::InternalServerError(),
+    }
+}
+
+/// 读取请求头的值，去掉首尾空白；缺失、非 ASCII 或为空时返回 None
+fn non_empty_header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
 }
 
 /// 获取客户端 IP 地址
-fn get_client_ip(req: &HttpRequest, connection_info: &actix_web::dev::ConnectionInfo) -> String {
+/// 依次尝试 X-Forwarded-For、X-Real-IP、CF-Connecting-IP，值为空时回退到下一个来源
+fn get_client_ip(
+    req: &HttpRequest,
+    connection_info: &actix_web::dev::ConnectionInfo,
+    trusted_hops: TrustedHops,
+) -> String {
     // 尝试从 X-Forwarded-For 头获取真实 IP
-    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For") {
-        if let Ok(forwarded_str) = forwarded_for.to_str() {
-            // X-Forwarded-For 可能包含多个 IP，取第一个
-            let first_ip = forwarded_str.split(',').next().unwrap_or("");
-            let trimmed = first_ip.trim();
-            if !trimmed.is_empty() {
-                return trimmed.to_string();
-            }
-        }
+    // X-Forwarded-For 可能包含多个 IP，按可信代理层数选出客户端
+    if let Some(ip) = non_empty_header(req, "X-Forwarded-For")
+        .and_then(|forwarded| forwarded_client_ip(forwarded, trusted_hops))
+    {
+        return ip.to_string();
     }
 
     // 尝试从 X-Real-IP 头获取
-    if let Some(real_ip) = req.headers().get("X-Real-IP") {
-        if let Ok(ip_str) = real_ip.to_str() {
-            return ip_str.to_string();
-        }
+    if let Some(ip) = non_empty_header(req, "X-Real-IP") {
+        return ip.to_string();
     }
 
     // 尝试从 CF-Connecting-IP (Cloudflare) 头获取
-    if let Some(cf_ip) = req.headers().get("CF-Connecting-IP") {
-        if let Ok(ip_str) = cf_ip.to_str() {
-            return ip_str.to_string();
-        }
+    if let Some(ip) = non_empty_header(req, "CF-Connecting-IP") {
+        return ip.to_string();
     }
 
-    // 回退到远程地址
-    connection_info
-        .realip_remote_addr()
+    // 回退到远程地址（actix 也会解析 X-Forwarded-For，为空时改用连接的对端地址）
+    // 配置了可信代理层数时，actix 取的是 X-Forwarded-For 的第一个条目，直接使用对端地址
+    let realip = match trusted_hops {
+        TrustedHops::All => connection_info.realip_remote_addr(),
+        TrustedHops::Count(_) => None,
+    };
+    realip
+        .map(|addr| addr.trim())
+        .filter(|addr| !addr.is_empty())
+        .or_else(|| connection_info.peer_addr())
         .map(|addr| addr.to_string())
         .unwrap_or_else(|| "unknown".to_string())
 }
 
+/// 启用 User-Agent 跟踪时，返回请求的 User-Agent
+fn tracked_user_agent(req: &HttpRequest, config: &AppConfig) -> Option<String> {
+    if !config.track_user_agent {
+        return None;
+    }
+    req.headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
 /// 首页路由 - 访问时自动增加一周（带 IP 检查）
 async fn index(
     db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
     req: HttpRequest,
     connection_info: actix_web::dev::ConnectionInfo,
 ) -> impl Responder {
-    let client_ip = get_client_ip(&req, &connection_info);
+    let client_ip = get_client_ip(&req, &connection_info, config.trusted_hops);
     log::info!("首页访问，来自 IP: {}", client_ip);
 
     // 尝试增加周数（带 IP 检查，异步处理不阻塞响应）
     let db_clone = db.clone();
     let client_ip_clone = client_ip.clone();
+    let user_agent = tracked_user_agent(&req, &config);
     tokio::spawn(async move {
-        match db_clone.increment_week_with_ip_check(client_ip_clone).await {
+        match db_clone.increment_week_with_ip_check(client_ip_clone, user_agent, Some("index")).await {
             Ok(true) => {
                 if let Ok(week_count) = db_clone.get_week_count().await {
                     log::info!("访问首页成功增加周数，当前周数: {}", week_count);
@@ -78,140 +304,2118 @@ async fn index(
         }
     });
 
-    // 返回首页内容
-    let html = include_str!("index.html");
+    // 返回首页内容（替换品牌相关的占位符）
+    let html = template::render(include_str!("index.html"), &[
+        ("title", &config.site_title),
+        ("event_name", &config.event_name),
+        ("accent_color", &config.accent_color),
+        // 禁用按钮接口时同时隐藏页面上的按钮
+        ("button_hidden", if config.disable_button { "hidden" } else { "" }),
+    ]);
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(html)
 }
 
 /// 获取当前数据 API（带 IP 检查，如果当天没有记录则自动增加一周）
+/// 提供 `callback` 参数时以 JSONP 形式返回
 async fn get_data(
     db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
     req: HttpRequest,
     connection_info: actix_web::dev::ConnectionInfo,
+    query: web::Query<DataQuery>,
 ) -> impl Responder {
-    let client_ip = get_client_ip(&req, &connection_info);
+    let callback = query.callback.as_deref();
+    if let Some(callback) = callback {
+        if !jsonp::is_valid_callback(callback) {
+            return HttpResponse::BadRequest().json(ApiResponse::error("无效的回调函数名"));
+        }
+    }
+
+    let client_ip = get_client_ip(&req, &connection_info, config.trusted_hops);
     log::info!("获取数据请求，来自 IP: {}", client_ip);
 
     // 尝试增加周数（带 IP 检查）
-    match db.increment_week_with_ip_check(client_ip.clone()).await {
-        Ok(_) => {
+    let user_agent = tracked_user_agent(&req, &config);
+    match db.increment_week_with_ip_check(client_ip.clone(), user_agent, Some("data")).await {
+        Ok(counted) => {
             // 无论是否增加，都返回当前周数
-            match db.get_week_count().await {
-                Ok(week_count) => {
+            // 读取失败时若有已知周数，则以 stale 标记返回，保证页面可用
+            match db.get_week_count_or_stale().await {
+                Ok((week_count, stale)) => {
                     log::info!("返回当前周数: {}", week_count);
-                    HttpResponse::Ok().json(ApiResponse {
-                        success: true,
-                        week_count,
-                        message: None,
-                    })
+                    let response = ApiResponse::current(week_count, &config)
+                        .stale(stale)
+                        .just_counted(counted);
+                    jsonp::respond(HttpResponse::Ok(), &response, callback)
                 }
                 Err(e) => {
                     log::error!("获取数据失败: {}", e);
-                    HttpResponse::InternalServerError().json(ApiResponse {
-                        success: false,
-                        week_count: 0,
-                        message: Some("获取数据失败".to_string()),
-                    })
+                    jsonp::respond(db_error_response(&e), &ApiResponse::error("获取数据失败"), callback)
                 }
             }
         }
         Err(e) => {
             log::error!("增加周数失败: {}", e);
             // 即使增加失败，也尝试返回当前周数
-            match db.get_week_count().await {
-                Ok(week_count) => HttpResponse::Ok().json(ApiResponse {
-                    success: true,
-                    week_count,
-                    message: None,
-                }),
-                Err(_) => HttpResponse::InternalServerError().json(ApiResponse {
-                    success: false,
-                    week_count: 0,
-                    message: Some("操作失败".to_string()),
-                }),
+            match db.get_week_count_or_stale().await {
+                Ok((week_count, stale)) => {
+                    let response = ApiResponse::current(week_count, &config)
+                        .stale(stale)
+                        .just_counted(false);
+                    jsonp::respond(HttpResponse::Ok(), &response, callback)
+                }
+                Err(e) => jsonp::respond(db_error_response(&e), &ApiResponse::error("操作失败"), callback),
             }
         }
     }
 }
 
+/// 记录访问 API：只执行带 IP 检查的增加，返回本次是否计数和当前周数
+async fn visit(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    connection_info: actix_web::dev::ConnectionInfo,
+    query: web::Query<VisitQuery>,
+) -> impl Responder {
+    let source = query.src.as_deref().unwrap_or("visit");
+    if !is_valid_source(source) {
+        return HttpResponse::BadRequest().json(ApiResponse::error(format!(
+            "src 只能包含字母、数字、- 和 _，且不超过 {} 个字符",
+            MAX_SOURCE_LEN
+        )));
+    }
+
+    let client_ip = get_client_ip(&req, &connection_info, config.trusted_hops);
+    let user_agent = tracked_user_agent(&req, &config);
+
+    let result = match db.increment_week_with_ip_check(client_ip.clone(), user_agent, Some(source)).await {
+        // 增加后周数已在缓存中，读取不会访问数据库
+        Ok(counted) => db.get_week_count().await.map(|week_count| (counted, week_count)),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok((counted, week_count)) => {
+            log::info!("记录访问，IP: {}，是否计数: {}", client_ip, counted);
+            HttpResponse::Ok().json(serde_json::json!({
+                "counted": counted,
+                "week_count": week_count,
+            }))
+        }
+        Err(e) => {
+            log::error!("记录访问失败: {}", e);
+            db_error_response(&e).json(ApiResponse::error("操作失败，请稍后重试"))
+        }
+    }
+}
+
+/// 查询调用方 IP 今天是否已经计数
+async fn caller_already_counted(
+    db: &Database,
+    config: &AppConfig,
+    req: &HttpRequest,
+    connection_info: &actix_web::dev::ConnectionInfo,
+) -> Result<(String, bool), DbError> {
+    let client_ip = get_client_ip(req, connection_info, config.trusted_hops);
+    let counted = db.check_ip_window(&client_ip).await?;
+    Ok((client_ip, counted))
+}
+
+/// 调用方状态 API：返回调用方 IP 今天是否已经计数（同时通过 `X-Already-Counted` 头返回）
+async fn get_me(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    connection_info: actix_web::dev::ConnectionInfo,
+) -> impl Responder {
+    match caller_already_counted(&db, &config, &req, &connection_info).await {
+        Ok((client_ip, counted)) => HttpResponse::Ok()
+            .insert_header(("X-Already-Counted", counted.to_string()))
+            .json(serde_json::json!({
+                "success": true,
+                "ip": client_ip,
+                "already_counted": counted,
+            })),
+        Err(e) => {
+            log::error!("查询 IP 计数状态失败: {}", e);
+            db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }))
+        }
+    }
+}
+
+/// 调用方状态 HEAD 请求：只返回 `X-Already-Counted` 头，没有响应体
+async fn head_me(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    connection_info: actix_web::dev::ConnectionInfo,
+) -> impl Responder {
+    match caller_already_counted(&db, &config, &req, &connection_info).await {
+        Ok((_, counted)) => HttpResponse::Ok()
+            .insert_header(("X-Already-Counted", counted.to_string()))
+            .finish(),
+        Err(e) => {
+            log::error!("查询 IP 计数状态失败: {}", e);
+            db_error_response(&e).finish()
+        }
+    }
+}
+
+/// 快照导入请求体的最大字节数
+const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+/// 幂等键的最大长度
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
 /// 增加周数 API（无 IP 检查，永远增加）
+/// 带 `Idempotency-Key` 请求头时，有效期内的重复请求只返回首次的结果
 async fn increment_week(
     db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    gate: web::Data<GlobalRateGate>,
+    req: HttpRequest,
 ) -> impl Responder {
     log::info!("收到增加周数请求（按钮点击）");
 
-    match db.increment_week().await {
-        Ok(week_count) => {
+    let idempotency_key = req
+        .headers()
+        .get("Idempotency-Key")
+        .map(|value| value.to_str().map(|key| key.trim().to_string()));
+    let idempotency_key = match idempotency_key {
+        None => None,
+        Some(Ok(key)) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => Some(key),
+        Some(_) => {
+            return HttpResponse::BadRequest().json(ApiResponse::error(format!(
+                "Idempotency-Key 不能为空且不超过 {} 字节",
+                MAX_IDEMPOTENCY_KEY_LEN
+            )));
+        }
+    };
+
+    // 有效期内的重试直接返回首次结果，不占用频率限制，也不记为重复点击
+    let ttl = chrono::Duration::seconds(config.idempotency_ttl_secs as i64);
+    if let Some(key) = &idempotency_key {
+        match db.idempotent_replay(key, ttl).await {
+            Ok(Some(week_count)) => return replayed_response(week_count),
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("查询幂等键失败: {}", e);
+                return db_error_response(&e).json(ApiResponse::error("操作失败，请稍后重试"));
+            }
+        }
+    }
+
+    // 启用加权增加时，按信任启发式降低可疑请求的权重
+    // 是否重复只看按钮点击记录，浏览首页不会让之后的点击被视为重复
+    let weight = if config.weighted_increments {
+        let client_ip = get_client_ip(&req, &req.connection_info(), config.trusted_hops);
+        let user_agent = req.headers().get("User-Agent").and_then(|v| v.to_str().ok());
+        match db.record_button_click(&client_ip).await {
+            Ok(repeat_ip) => trust::increment_weight(user_agent, repeat_ip),
+            Err(e) => {
+                log::error!("记录按钮点击失败: {}", e);
+                return db_error_response(&e).json(ApiResponse::error("操作失败，请稍后重试"));
+            }
+        }
+    } else {
+        1.0
+    };
+
+    // 权重为 0 时不会增加，直接返回当前周数
+    if db::weight_units(weight) == 0 {
+        return match db.get_week_count().await {
+            Ok(week_count) => HttpResponse::Ok().json(ApiResponse::ok(week_count)),
+            Err(e) => {
+                log::error!("获取数据失败: {}", e);
+                db_error_response(&e).json(ApiResponse::error("操作失败，请稍后重试"))
+            }
+        };
+    }
+
+    // 全局频率限制：整个服务器上两次按钮增加之间至少间隔配置的时间
+    let slot = match gate.try_acquire() {
+        Ok(slot) => slot,
+        Err(wait_ms) => {
+            log::info!("按钮增加过于频繁，需等待 {} 毫秒", wait_ms);
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", wait_ms.div_ceil(1000).to_string()))
+                .json(ApiResponse::error("点击过于频繁，请稍后再试"));
+        }
+    };
+
+    let result = match idempotency_key {
+        Some(key) => db.increment_week_idempotent(key, ttl, weight, Some("button")).await,
+        None if config.weighted_increments => db
+            .increment_weighted(weight, Some("button"))
+            .await
+            .map(|week_count| (week_count, false)),
+        None => db
+            .increment_week(Some("button"))
+            .await
+            .map(|week_count| (week_count, false)),
+    };
+
+    // 没有提交增加（并发重放或数据库出错）时归还放行
+    if !matches!(result, Ok((_, false))) {
+        gate.release(slot);
+    }
+
+    match result {
+        Ok((week_count, true)) => replayed_response(week_count),
+        Ok((week_count, false)) => {
             log::info!("成功增加周数，当前周数: {}", week_count);
-            HttpResponse::Ok().json(ApiResponse {
-                success: true,
-                week_count,
-                message: None,
-            })
+            HttpResponse::Ok().json(ApiResponse::ok(week_count))
         }
         Err(e) => {
             log::error!("增加周数失败: {}", e);
-            HttpResponse::InternalServerError().json(ApiResponse {
+            db_error_response(&e).json(ApiResponse::error("操作失败，请稍后重试"))
+        }
+    }
+}
+
+/// 幂等请求的重放响应
+fn replayed_response(week_count: u64) -> HttpResponse {
+    log::info!("重复的幂等请求，返回首次结果: {}", week_count);
+    HttpResponse::Ok()
+        .insert_header(("Idempotent-Replayed", "true"))
+        .json(ApiResponse::ok(week_count))
+}
+
+/// 目标日期 API（从预设起始时间加上当前周数）
+async fn get_date(db: web::Data<Arc<Database>>) -> impl Responder {
+    let week_count = match db.get_week_count().await {
+        Ok(week_count) => week_count,
+        Err(e) => {
+            log::error!("获取数据失败: {}", e);
+            return db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }));
+        }
+    };
+
+    match db::calculate_date_from_weeks(week_count) {
+        Ok(date) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "week_count": week_count,
+            "date": date.to_rfc3339(),
+        })),
+        Err(e) => {
+            log::warn!("计算目标日期失败: {}", e);
+            HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                "success": false,
+                "week_count": week_count,
+                "message": "日期超出范围",
+            }))
+        }
+    }
+}
+
+/// 下一个里程碑 API（返回当前周数之上的下一个里程碑及距离）
+async fn get_milestone(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+) -> impl Responder {
+    let week_count = match db.get_week_count().await {
+        Ok(week_count) => week_count,
+        Err(e) => {
+            log::error!("获取数据失败: {}", e);
+            return db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }));
+        }
+    };
+
+    match config.milestones.next_after(week_count) {
+        Some(milestone) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "week_count": week_count,
+            "next_milestone": milestone,
+            "remaining": milestone - week_count,
+            "all_passed": false,
+        })),
+        None => {
+            let message = if config.milestones.is_empty() {
+                "未配置里程碑"
+            } else {
+                "已达成所有里程碑"
+            };
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "week_count": week_count,
+                "next_milestone": null,
+                "remaining": null,
+                "all_passed": true,
+                "message": message,
+            }))
+        }
+    }
+}
+
+/// PWA 清单（复用页面品牌配置）
+async fn manifest(config: web::Data<AppConfig>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/manifest+json")
+        .json(serde_json::json!({
+            "name": config.site_title,
+            "short_name": config.event_name,
+            "start_url": "/",
+            "display": "standalone",
+            "background_color": "#ffffff",
+            "theme_color": config.accent_color,
+            "icons": [{
+                "src": "/icon.svg",
+                "sizes": "any",
+                "type": "image/svg+xml",
+                "purpose": "any"
+            }]
+        }))
+}
+
+/// PWA 图标（使用页面主题色）
+async fn icon(config: web::Data<AppConfig>) -> impl Responder {
+    let svg = template::render(include_str!("icon.svg"), &[
+        ("accent_color", &config.accent_color),
+    ]);
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(svg)
+}
+
+/// 周数徽章（SVG），带基于内容的 ETag，内容未变时对 `If-None-Match` 返回 304
+async fn get_badge(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let week_count = match db.get_week_count().await {
+        Ok(week_count) => week_count,
+        Err(e) => {
+            log::error!("获取周数失败: {}", e);
+            return db_error_response(&e).finish();
+        }
+    };
+
+    let value = format!("{} 周", week_count);
+    let etag = badge::etag(&config.event_name, &value, &config.accent_color);
+    let cache_control = format!("public, max-age={}", config.badge_max_age_secs);
+
+    let not_modified = req
+        .headers()
+        .get("If-None-Match")
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|header| badge::if_none_match(header, &etag));
+    if not_modified {
+        return HttpResponse::NotModified()
+            .insert_header(("ETag", etag))
+            .insert_header(("Cache-Control", cache_control))
+            .finish();
+    }
+
+    let svg = badge::render(&config.event_name, &value, &config.accent_color);
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .insert_header(("ETag", etag))
+        .insert_header(("Cache-Control", cache_control))
+        .body(svg)
+}
+
+/// Service Worker 脚本（离线时显示最后一次获取到的周数）
+async fn service_worker() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .body(include_str!("sw.js"))
+}
+
+/// 周数变化 API（返回指定时间点以来的增加次数及前后周数）
+async fn get_delta(
+    db: web::Data<Arc<Database>>,
+    query: web::Query<DeltaQuery>,
+) -> impl Responder {
+    let since = match chrono::DateTime::parse_from_rfc3339(&query.since) {
+        Ok(since) => since.with_timezone(&chrono::Utc),
+        Err(e) => {
+            log::warn!("无效的时间参数 {}: {}", query.since, e);
+            return HttpResponse::BadRequest().json(DeltaResponse {
+                success: false,
+                delta: None,
+                unit: None,
+                message: Some("since 参数必须是 RFC3339 格式的时间".to_string()),
+            });
+        }
+    };
+
+    match db.delta_since(since).await {
+        Ok(delta) => HttpResponse::Ok().json(DeltaResponse {
+            success: true,
+            delta: Some(delta),
+            unit: Some(STATS_UNIT),
+            message: None,
+        }),
+        Err(e) => {
+            log::error!("获取周数变化失败: {}", e);
+            db_error_response(&e).json(DeltaResponse {
                 success: false,
-                week_count: 0,
-                message: Some("操作失败，请稍后重试".to_string()),
+                delta: None,
+                unit: None,
+                message: Some("获取数据失败".to_string()),
             })
         }
     }
 }
 
-/// 健康检查 API
-async fn health_check() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "service": "teacon-counter"
-    }))
+/// 增长速率 API（最近 N 天的平均每日增加次数）
+async fn get_growth_rate(
+    db: web::Data<Arc<Database>>,
+    query: web::Query<RateQuery>,
+) -> impl Responder {
+    if query.days == 0 || query.days > MAX_RATE_DAYS {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": format!("days 参数必须在 1 到 {} 之间", MAX_RATE_DAYS),
+        }));
+    }
+
+    match db.growth_rate(query.days).await {
+        Ok(rate) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "days": query.days,
+            "rate": rate,
+            "unit": STATS_UNIT,
+        })),
+        Err(e) => {
+            log::error!("计算增长速率失败: {}", e);
+            db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }))
+        }
+    }
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // 初始化日志
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+/// 来源统计 API：每个来源的增加次数，按次数从多到少排序
+async fn get_source_breakdown(db: web::Data<Arc<Database>>) -> impl Responder {
+    match db.get_source_breakdown().await {
+        Ok(breakdown) => {
+            let sources: Vec<_> = breakdown
+                .into_iter()
+                .map(|(source, count)| serde_json::json!({
+                    "source": source,
+                    "count": count,
+                }))
+                .collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "sources": sources,
+                "unit": STATS_UNIT,
+            }))
+        }
+        Err(e) => {
+            log::error!("获取来源统计失败: {}", e);
+            db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }))
+        }
+    }
+}
 
-    // 数据库路径
-    let db_path = std::env::var("DB_PATH").unwrap_or_else(|_| "./data/db".to_string());
+/// 与上一季对比 API：本季开始后经过相同时间时，上一季的周数和差值
+/// 本季开始时间取周数的预设起始时间，不受历史记录清理或删除的影响；未加载上一季数据时返回 404
+async fn get_vs_previous(
+    db: web::Data<Arc<Database>>,
+    previous: Option<web::Data<PreviousSeason>>,
+) -> impl Responder {
+    let Some(previous) = previous else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "available": false,
+            "message": "未加载上一季数据",
+        }));
+    };
 
-    // 初始化数据库
-    let db = match Database::new(&db_path) {
-        Ok(database) => {
-            log::info!("数据库初始化成功，路径: {}", db_path);
-            Arc::new(database)
+    match db.get_week_count().await {
+        Ok(week_count) => {
+            let elapsed = (db.now() - db::base_time()).max(chrono::Duration::zero());
+            let previous_count = previous.count_at(elapsed);
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "available": true,
+                "elapsed_secs": elapsed.num_seconds(),
+                "week_count": week_count,
+                "previous_week_count": previous_count,
+                "difference": week_count as i128 - previous_count as i128,
+            }))
         }
         Err(e) => {
-            log::error!("数据库初始化失败: {}", e);
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("数据库初始化失败: {}", e),
-            ));
+            log::error!("与上一季对比失败: {}", e);
+            db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }))
         }
-    };
+    }
+}
 
-    // 服务器地址
-    let bind_address = std::env::var("BIND_ADDRESS")
-        .unwrap_or_else(|_| "0.0.0.0:8080".to_string());
+/// 国家统计 API：首页访问按国家的次数（需配置 `GEOIP_DB_PATH`）
+async fn get_country_breakdown(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+) -> impl Responder {
+    match db.get_country_breakdown().await {
+        Ok(breakdown) => {
+            let countries: Vec<_> = breakdown
+                .into_iter()
+                .map(|(country, count)| serde_json::json!({
+                    "country": country,
+                    "count": count,
+                }))
+                .collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "enabled": config.geoip_db_path.is_some(),
+                "countries": countries,
+            }))
+        }
+        Err(e) => {
+            log::error!("获取国家统计失败: {}", e);
+            db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }))
+        }
+    }
+}
 
-    log::info!("启动服务器，监听地址: {}", bind_address);
+/// 按小时分布 API：全部增加按一天中的小时（`DISPLAY_UTC_OFFSET` 时区）统计
+async fn get_hourly_distribution(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+) -> impl Responder {
+    match db.increments_by_hour(config.display_utc_offset).await {
+        Ok(hours) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "utc_offset": config.display_utc_offset.to_string(),
+            "hours": hours,
+            "unit": STATS_UNIT,
+        })),
+        Err(e) => {
+            log::error!("获取按小时分布失败: {}", e);
+            db_error_response(&e).json(serde_json::json!({
+                "success": false,
+                "message": "获取数据失败",
+            }))
+        }
+    }
+}
 
-    // 启动 HTTP 服务器
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+/// 指标 API，按 Accept 头输出 OpenMetrics 或 Prometheus 文本格式
+async fn get_metrics(
+    db: web::Data<Arc<Database>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let snapshot = match db.metrics_snapshot().await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            log::error!("收集指标失败: {}", e);
+            return db_error_response(&e).finish();
+        }
+    };
 
-        App::new()
-            .app_data(web::Data::new(db.clone()))
-            .wrap(cors)
-            .route("/", web::get().to(index))
-            .route("/api/data", web::get().to(get_data))
-            .route("/api/increment", web::post().to(increment_week))
-            .route("/health", web::get().to(health_check))
-    })
-    .bind(&bind_address)?
-    .run()
-    .await
+    let accept = req
+        .headers()
+        .get("Accept")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if metrics::wants_openmetrics(accept) {
+        HttpResponse::Ok()
+            .content_type(metrics::OPENMETRICS_CONTENT_TYPE)
+            .body(metrics::render_openmetrics(&snapshot))
+    } else {
+        HttpResponse::Ok()
+            .content_type(metrics::PROMETHEUS_CONTENT_TYPE)
+            .body(metrics::render_prometheus(&snapshot))
+    }
+}
+
+/// Pushgateway 格式的指标 API，供无法被抓取的环境由外部任务转推
+async fn get_pushgateway_payload(db: web::Data<Arc<Database>>) -> impl Responder {
+    match db.metrics_snapshot().await {
+        Ok(snapshot) => HttpResponse::Ok()
+            .content_type(pushgateway::CONTENT_TYPE)
+            .body(pushgateway::payload(&snapshot)),
+        Err(e) => {
+            log::error!("收集指标失败: {}", e);
+            db_error_response(&e).finish()
+        }
+    }
+}
+
+/// 校验管理令牌 API（不执行任何操作，仅返回令牌是否有效）
+async fn admin_verify(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    HttpResponse::Ok().json(admin::AdminResponse {
+        success: true,
+        message: None,
+    })
+}
+
+/// 重置周数 API（需要管理令牌）：重置前的数据写入档案，`RESET_UNDO_GRACE_SECS` 内可以撤销
+async fn reset_weeks(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let grace = chrono::Duration::seconds(config.reset_undo_grace_secs as i64);
+    match db.reset_weeks(grace).await {
+        Ok(reset) => {
+            log::warn!("管理员重置周数，重置前: {}", reset.previous_count);
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "previous_count": reset.previous_count,
+                "undo_deadline": reset.undo_deadline.to_rfc3339(),
+            }))
+        }
+        Err(e) => {
+            log::error!("重置周数失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("重置周数失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 撤销重置 API（需要管理令牌）：撤销期限内恢复最近一次重置前的周数，超过期限返回 410
+async fn undo_reset(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let grace = chrono::Duration::seconds(config.reset_undo_grace_secs as i64);
+    match db.undo_reset(grace).await {
+        Ok(UndoReset::Restored { week_count, .. }) => {
+            log::warn!("管理员撤销重置，周数恢复为 {}", week_count);
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "week_count": week_count,
+            }))
+        }
+        Ok(UndoReset::Expired { reset_at }) => HttpResponse::Gone().json(admin::AdminResponse {
+            success: false,
+            message: Some(format!("重置发生于 {}，已超过撤销期限", reset_at.to_rfc3339())),
+        }),
+        Ok(UndoReset::NothingToUndo) => HttpResponse::NotFound().json(admin::AdminResponse {
+            success: false,
+            message: Some("没有可撤销的重置".to_string()),
+        }),
+        Err(e) => {
+            log::error!("撤销重置失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("撤销重置失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 周数修正 API（需要管理令牌）：按给定量修正周数并记录原因
+async fn adjust_week(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<AdjustRequest>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let reason = body.reason.trim();
+    if body.delta == 0 || reason.is_empty() || reason.chars().count() > MAX_ADJUST_REASON_LEN {
+        return HttpResponse::BadRequest().json(admin::AdminResponse {
+            success: false,
+            message: Some(format!(
+                "delta 不能为 0，reason 不能为空且不超过 {} 个字符",
+                MAX_ADJUST_REASON_LEN
+            )),
+        });
+    }
+
+    match db.adjust_week(body.delta, reason).await {
+        Ok(adjustment) => {
+            // 修正量超过当前周数时只减到 0，在响应中明确告知
+            let clamped = adjustment.applied != body.delta;
+            log::warn!(
+                "管理员修正周数 {}（实际 {}），{} -> {}，原因: {}",
+                body.delta,
+                adjustment.applied,
+                adjustment.previous_count,
+                adjustment.week_count,
+                reason
+            );
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "previous_count": adjustment.previous_count,
+                "week_count": adjustment.week_count,
+                "requested": body.delta,
+                "applied": adjustment.applied,
+                "clamped": clamped,
+            }))
+        }
+        Err(e) => {
+            log::error!("修正周数失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("操作失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 读取并清零本期增加次数 API（需要管理令牌），供定期报告使用
+async fn take_period_count(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    match db.take_period_count().await {
+        Ok(count) => {
+            log::info!("读取并清零本期增加次数: {}", count);
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "period_count": count,
+                "unit": STATS_UNIT,
+            }))
+        }
+        Err(e) => {
+            log::error!("读取本期增加次数失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("操作失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 导出完整数据快照 API（需要管理令牌）
+async fn export_snapshot(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    match db.export_snapshot().await {
+        Ok(snapshot) => HttpResponse::Ok().json(snapshot),
+        Err(e) => {
+            log::error!("导出快照失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("操作失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 从快照恢复数据 API（需要管理令牌），快照中的每棵树整体替换
+async fn import_snapshot(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<db::Snapshot>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    log::warn!("管理员导入快照，包含 {} 棵树", body.trees.len());
+    match db.import_snapshot(body.into_inner()).await {
+        Ok(()) => HttpResponse::Ok().json(admin::AdminResponse {
+            success: true,
+            message: None,
+        }),
+        Err(e @ (DbError::InvalidSnapshot(_) | DbError::UnknownTree(_))) => {
+            HttpResponse::BadRequest().json(admin::AdminResponse {
+                success: false,
+                message: Some(e.to_string()),
+            })
+        }
+        Err(e) => {
+            log::error!("导入快照失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("操作失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 从历史记录重建派生统计 API（需要管理令牌）
+async fn rebuild_derived(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    log::warn!("管理员触发重建派生统计");
+    match db.rebuild_derived().await {
+        Ok(()) => HttpResponse::Ok().json(admin::AdminResponse {
+            success: true,
+            message: None,
+        }),
+        Err(e) => {
+            log::error!("重建派生统计失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("操作失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 历史记录列表 API（需要管理令牌，按时间倒序）
+async fn list_history(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let before = match query.before.as_deref().map(db::parse_history_key) {
+        Some(None) => {
+            return HttpResponse::BadRequest().json(admin::AdminResponse {
+                success: false,
+                message: Some("before 参数不是有效的历史记录键".to_string()),
+            });
+        }
+        Some(Some(key)) => Some(key),
+        None => None,
+    };
+
+    let limit = query.limit.min(MAX_HISTORY_LIMIT);
+    match db.list_history(limit, before).await {
+        Ok(entries) => {
+            let entries: Vec<_> = entries
+                .into_iter()
+                .map(|(key, entry)| serde_json::json!({
+                    "key": key,
+                    "time": entry.time,
+                    "week_count": entry.week_count,
+                    "ip": entry.ip,
+                    "source": entry.source,
+                    "weight": entry.units as f64 / db::COUNT_UNIT as f64,
+                    "country": entry.country,
+                }))
+                .collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "entries": entries,
+            }))
+        }
+        Err(e) => {
+            log::error!("获取历史记录失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("获取数据失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 删除单条历史记录 API（需要管理令牌，可选同时将周数减一）
+async fn delete_history(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DeleteHistoryQuery>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let key_text = path.into_inner();
+    let Some(key) = db::parse_history_key(&key_text) else {
+        return HttpResponse::BadRequest().json(admin::AdminResponse {
+            success: false,
+            message: Some("无效的历史记录键".to_string()),
+        });
+    };
+
+    match db.delete_history(key, query.decrement).await {
+        Ok(Some((entry, week_count))) => {
+            // 修改审计记录的操作需要留痕
+            log::warn!(
+                "管理员删除历史记录 {}（时间: {}，IP: {}，减少周数: {}），当前周数: {}",
+                key_text,
+                entry.time.to_rfc3339(),
+                entry.ip.as_deref().unwrap_or("-"),
+                query.decrement,
+                week_count
+            );
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "removed": {
+                    "key": key_text,
+                    "time": entry.time,
+                    "week_count": entry.week_count,
+                    "ip": entry.ip,
+                    "source": entry.source,
+                    "weight": entry.units as f64 / db::COUNT_UNIT as f64,
+                    "country": entry.country,
+                },
+                "week_count": week_count,
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().json(admin::AdminResponse {
+            success: false,
+            message: Some("历史记录不存在".to_string()),
+        }),
+        Err(e) => {
+            log::error!("删除历史记录 {} 失败: {}", key_text, e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("操作失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 查询某个 IP 的访问记录 API（需要管理令牌）
+async fn get_ip_info(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let ip = path.into_inner();
+    match db.ip_info(&ip).await {
+        Ok(info) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "ip": ip,
+            "count": info.count,
+            "last_click_time": info.last_click_time,
+            "user_agent": info.user_agent,
+        })),
+        Err(e) => {
+            log::error!("查询 IP {} 的记录失败: {}", ip, e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("获取数据失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 查询指定树中某个键的原始字节 API（需要管理令牌），以十六进制返回
+async fn get_raw(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    query: web::Query<RawQuery>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    match db.get_raw(&query.tree, &query.key).await {
+        Ok(Some(value)) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "tree": query.tree,
+            "key": query.key,
+            "len": value.len(),
+            "hex": db::encode_hex(&value),
+        })),
+        Ok(None) => HttpResponse::NotFound().json(admin::AdminResponse {
+            success: false,
+            message: Some("键不存在".to_string()),
+        }),
+        Err(DbError::UnknownTree(tree)) => HttpResponse::BadRequest().json(admin::AdminResponse {
+            success: false,
+            message: Some(format!("未知的树: {}", tree)),
+        }),
+        Err(e) => {
+            log::error!("读取原始数据失败: {}", e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("获取数据失败".to_string()),
+            })
+        }
+    }
+}
+
+/// HTTP 服务器实际启动的工作线程数
+struct HttpWorkers(usize);
+
+/// 运行时诊断 API（需要管理令牌）：阻塞数据库操作的并发状态和当前工作线程运行时的指标
+/// 用于在高峰期判断慢请求是受数据库还是线程池限制
+async fn get_runtime(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    http_workers: web::Data<HttpWorkers>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    // actix 的每个工作线程运行独立的 tokio 运行时，这里只反映处理本请求的工作线程
+    let metrics = tokio::runtime::Handle::current().metrics();
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "blocking": db.blocking_stats(),
+        "db_timeout_ms": config.db_timeout_ms,
+        "http_workers": http_workers.0,
+        "current_worker_runtime": {
+            "num_workers": metrics.num_workers(),
+            "num_alive_tasks": metrics.num_alive_tasks(),
+            "global_queue_depth": metrics.global_queue_depth(),
+        },
+    }))
+}
+
+/// 查询某个 IP 累计增加次数 API（需要管理令牌）
+async fn get_ip_count(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> impl Responder {
+    if !admin::is_authorized(&req, &config) {
+        return admin::unauthorized();
+    }
+
+    let ip = path.into_inner();
+    match db.ip_increment_count(&ip).await {
+        Ok(count) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "ip": ip,
+            "count": count,
+        })),
+        Err(e) => {
+            log::error!("查询 IP {} 的计数失败: {}", ip, e);
+            db_error_response(&e).json(admin::AdminResponse {
+                success: false,
+                message: Some("获取数据失败".to_string()),
+            })
+        }
+    }
+}
+
+/// 周数变化事件流（SSE），空闲时定期发送心跳注释以免代理断开连接
+async fn event_stream(
+    db: web::Data<Arc<Database>>,
+    config: web::Data<AppConfig>,
+    broadcaster: web::Data<EventBroadcaster>,
+) -> impl Responder {
+    // 先订阅再读取当前周数，避免错过两者之间的增加
+    let receiver = broadcaster.subscribe();
+    match db.get_week_count().await {
+        Ok(week_count) => {
+            let heartbeat = std::time::Duration::from_secs(config.sse_heartbeat_secs);
+            HttpResponse::Ok()
+                .content_type("text/event-stream")
+                .insert_header(("Cache-Control", "no-cache"))
+                // 关闭 nginx 的响应缓冲，使事件立即送达
+                .insert_header(("X-Accel-Buffering", "no"))
+                .streaming(sse::event_stream(receiver, week_count, heartbeat))
+        }
+        Err(e) => {
+            log::error!("获取周数失败: {}", e);
+            db_error_response(&e).finish()
+        }
+    }
+}
+
+/// 里程碑事件流 API（SSE）：只在周数跨过配置的里程碑时推送
+async fn milestone_stream(
+    config: web::Data<AppConfig>,
+    milestones: web::Data<MilestoneBroadcaster>,
+) -> impl Responder {
+    let heartbeat = std::time::Duration::from_secs(config.sse_heartbeat_secs);
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(sse::milestone_stream(milestones.subscribe(), heartbeat))
+}
+
+/// 服务器限制 API：返回客户端组织请求时需要遵守的数值限制（不含敏感配置）
+async fn get_limits(config: web::Data<AppConfig>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "max_body_bytes": config.max_body_bytes,
+        "button_min_interval_ms": config.button_min_interval_ms,
+        "max_history_limit": MAX_HISTORY_LIMIT,
+        "max_rate_days": MAX_RATE_DAYS,
+        "max_adjust_reason_len": MAX_ADJUST_REASON_LEN,
+        "max_callback_len": jsonp::MAX_CALLBACK_LEN,
+        "max_import_bytes": MAX_IMPORT_BYTES,
+        "freeze_at": config.freeze_at.map(|time| time.to_rfc3339()),
+    }))
+}
+
+/// 健康检查 API
+async fn health_check() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "healthy",
+        "service": "teacon-counter"
+    }))
+}
+
+/// 按配置打开 GeoIP 数据库，打开失败时只记录日志，不统计国家也不影响计数
+fn open_geoip(config: &AppConfig) -> Option<Arc<dyn geoip::CountryLookup>> {
+    let path = config.geoip_db_path.as_deref()?;
+    match geoip::GeoIp::open(path) {
+        Ok(geoip) => {
+            log::info!("已加载 GeoIP 数据库: {}", path);
+            Some(Arc::new(geoip))
+        }
+        Err(e) => {
+            log::warn!("加载 GeoIP 数据库 {} 失败，不统计国家: {}", path, e);
+            None
+        }
+    }
+}
+
+/// 当前 API 版本，通过 `X-API-Version` 响应头返回
+const API_VERSION: &str = "1";
+
+/// 注册挂载在指定前缀下的 API 路由
+/// 同时挂载在 `/api/v1`（当前版本）和 `/api`（兼容旧客户端）下
+/// `DISABLE_BUTTON` 启用时不注册 `/increment`，请求该路径返回 404
+fn api_scope(prefix: &str, config: &AppConfig) -> actix_web::Scope {
+    let scope = web::scope(prefix).route("/data", web::get().to(get_data));
+    let scope = if config.disable_button {
+        scope
+    } else {
+        scope.route("/increment", web::post().to(increment_week))
+    };
+
+    scope
+        .route("/visit", web::post().to(visit))
+        .route("/me", web::get().to(get_me))
+        .route("/me", web::head().to(head_me))
+        .route("/date", web::get().to(get_date))
+        .route("/limits", web::get().to(get_limits))
+        .route("/badge.svg", web::get().to(get_badge))
+        .route("/delta", web::get().to(get_delta))
+        .route("/events", web::get().to(event_stream))
+        .route("/stream/milestones", web::get().to(milestone_stream))
+        .route("/milestone", web::get().to(get_milestone))
+        .route("/stats/rate", web::get().to(get_growth_rate))
+        .route("/stats/vs-previous", web::get().to(get_vs_previous))
+        .route("/stats/sources", web::get().to(get_source_breakdown))
+        .route("/stats/countries", web::get().to(get_country_breakdown))
+        .route("/stats/hourly-distribution", web::get().to(get_hourly_distribution))
+        .route("/admin/verify", web::post().to(admin_verify))
+        .route("/admin/rebuild", web::post().to(rebuild_derived))
+        .route("/admin/period/take", web::post().to(take_period_count))
+        .route("/admin/export", web::get().to(export_snapshot))
+        .service(
+            // 快照可能远大于普通请求体，单独放宽大小限制
+            web::resource("/admin/import")
+                .app_data(web::JsonConfig::default().limit(MAX_IMPORT_BYTES))
+                .route(web::post().to(import_snapshot)),
+        )
+        .route("/adjust", web::post().to(adjust_week))
+        .route("/reset", web::post().to(reset_weeks))
+        .route("/reset/undo", web::post().to(undo_reset))
+        .route("/history", web::get().to(list_history))
+        .route("/history/{key}", web::delete().to(delete_history))
+        .route("/ip/{addr}", web::get().to(get_ip_info))
+        .route("/ip/{addr}/count", web::get().to(get_ip_count))
+        .route("/debug/raw", web::get().to(get_raw))
+        .route("/debug/runtime", web::get().to(get_runtime))
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // 初始化日志
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+
+    // 读取配置
+    let config = AppConfig::from_env();
+    let db_path = &config.db_path;
+
+    // 初始化数据库
+    let broadcaster = EventBroadcaster::new();
+    let milestone_broadcaster = MilestoneBroadcaster::new(config.milestones.clone());
+    let db = match Database::new(db_path, config.initial_count) {
+        Ok(database) => {
+            log::info!("数据库初始化成功，路径: {}", db_path);
+            let mut hooks = HookRegistry::from_config(&config);
+            hooks.register(broadcaster.clone());
+            hooks.register(milestone_broadcaster.clone());
+            Arc::new(
+                database
+                    .with_hooks(hooks)
+                    .with_max_tx_attempts(config.tx_max_attempts)
+                    .with_max_blocking_ops(config.db_max_concurrency)
+                    .with_op_timeout(std::time::Duration::from_millis(config.db_timeout_ms))
+                    .with_freeze_at(config.freeze_at)
+                    .with_geoip(open_geoip(&config)),
+            )
+        }
+        Err(e) => {
+            log::error!("数据库初始化失败: {}", e);
+            return Err(std::io::Error::other(format!("数据库初始化失败: {}", e)));
+        }
+    };
+
+    // 预热周数缓存，使第一个请求无需读取数据库
+    if config.warm_cache {
+        match db.warm_cache().await {
+            Ok(week_count) => log::info!("周数缓存预热完成，当前周数: {}", week_count),
+            Err(e) => log::warn!("周数缓存预热失败: {}", e),
+        }
+    }
+
+    // 后台定期清理过期的幂等键和按钮点击记录
+    {
+        let db = db.clone();
+        let ttl = config.idempotency_ttl_secs;
+        tokio::spawn(async move {
+            // 清理间隔取有效期，但不超过一小时
+            let period = std::time::Duration::from_secs(ttl.min(60 * 60));
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let older_than = chrono::Utc::now() - chrono::Duration::seconds(ttl as i64);
+                match db.prune_idempotency_keys(older_than).await {
+                    Ok(pruned) => log::info!("清理过期幂等键 {} 个", pruned),
+                    Err(e) => log::warn!("清理过期幂等键失败: {}", e),
+                }
+                match db.prune_button_clicks().await {
+                    Ok(pruned) => log::info!("清理过期按钮点击记录 {} 个", pruned),
+                    Err(e) => log::warn!("清理过期按钮点击记录失败: {}", e),
+                }
+            }
+        });
+    }
+
+    // 定期推送指标到 Pushgateway（可选）
+    if let Some(url) = &config.pushgateway_url {
+        match pushgateway::Pusher::new(url, &config.pushgateway_job) {
+            Ok(pusher) => {
+                log::info!("每 {} 秒推送指标到 Pushgateway: {}", config.pushgateway_interval_secs, url);
+                let period = std::time::Duration::from_secs(config.pushgateway_interval_secs);
+                tokio::spawn(pusher.run(db.clone(), period));
+            }
+            Err(e) => log::warn!("{}，不推送指标", e),
+        }
+    }
+
+    // 启动 OpenTelemetry 指标导出（可选，需要 `otel` 特性）
+    #[cfg(feature = "otel")]
+    let meter_provider = match &config.otel_endpoint {
+        Some(endpoint) => match telemetry::start(db.clone()) {
+            Ok(provider) => {
+                log::info!("OpenTelemetry 指标导出到: {}", endpoint);
+                Some(provider)
+            }
+            Err(e) => {
+                log::warn!("启动 OpenTelemetry 指标导出失败: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "otel"))]
+    if config.otel_endpoint.is_some() {
+        log::warn!("已设置 OTEL_EXPORTER_OTLP_ENDPOINT，但编译时未启用 otel 特性，不导出指标");
+    }
+    #[cfg(feature = "otel")]
+    let http_metrics = meter_provider
+        .as_ref()
+        .map(|provider| web::Data::new(telemetry::HttpMetrics::new(provider)));
+
+    // 加载上一季时间序列（可选）
+    let previous_season = config.previous_season_file.as_deref().and_then(|path| {
+        match PreviousSeason::load(path) {
+            Ok(season) => {
+                log::info!("已加载上一季数据: {}", path);
+                Some(web::Data::new(season))
+            }
+            Err(e) => {
+                log::warn!("加载上一季数据 {} 失败: {}", path, e);
+                None
+            }
+        }
+    });
+
+    // 服务器地址
+    let bind_address = config.bind_address.clone();
+    let gate = web::Data::new(GlobalRateGate::new(config.button_min_interval_ms));
+    // 与 actix 的默认值一致，按可用 CPU 数启动工作线程，并把实际的数量提供给诊断接口
+    let http_workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let http_workers_data = web::Data::new(HttpWorkers(http_workers));
+    let config = web::Data::new(config);
+    let broadcaster = web::Data::new(broadcaster);
+    let milestone_broadcaster = web::Data::new(milestone_broadcaster);
+    let json_config = web::JsonConfig::default().limit(config.max_body_bytes);
+
+    log::info!("启动服务器，监听地址: {}", bind_address);
+
+    // 启动 HTTP 服务器
+    HttpServer::new(move || {
+        let cors = Cors::permissive();
+
+        let mut app = App::new();
+        if let Some(previous_season) = &previous_season {
+            app = app.app_data(previous_season.clone());
+        }
+        #[cfg(feature = "otel")]
+        if let Some(http_metrics) = &http_metrics {
+            app = app.app_data(http_metrics.clone());
+        }
+
+        let app = app
+            .app_data(web::Data::new(db.clone()))
+            .app_data(config.clone())
+            .app_data(gate.clone())
+            .app_data(http_workers_data.clone())
+            .app_data(broadcaster.clone())
+            .app_data(milestone_broadcaster.clone())
+            .app_data(json_config.clone())
+            .wrap(cors)
+            .wrap(middleware::DefaultHeaders::new().add(("X-API-Version", API_VERSION)))
+            // 去掉路径末尾的斜杠，使 `/api/data/` 与 `/api/data` 路由到同一个处理函数
+            .wrap(middleware::NormalizePath::trim())
+            // 小于 `COMPRESSION_MIN_BYTES` 的响应不压缩
+            .wrap(middleware::from_fn(compression::compress))
+            .route("/", web::get().to(index))
+            .route("/manifest.json", web::get().to(manifest))
+            .route("/icon.svg", web::get().to(icon))
+            .route("/sw.js", web::get().to(service_worker))
+            // 版本化路由优先注册，未带版本的旧路由作为兼容别名
+            .service(api_scope("/api/v1", &config))
+            .service(api_scope("/api", &config))
+            .route("/metrics", web::get().to(get_metrics))
+            .route("/metrics/pushgateway", web::get().to(get_pushgateway_payload))
+            .route("/health", web::get().to(health_check));
+        // 最外层记录请求次数和耗时，包含压缩等中间件的开销
+        #[cfg(feature = "otel")]
+        let app = app.wrap(middleware::from_fn(telemetry::record_request));
+        app
+    })
+    .workers(http_workers)
+    .bind(&bind_address)?
+    .run()
+    .await?;
+
+    // 退出前导出最后一批指标
+    #[cfg(feature = "otel")]
+    if let Some(provider) = meter_provider {
+        if let Err(e) = provider.shutdown() {
+            log::warn!("关闭 OpenTelemetry 指标导出失败: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test};
+
+    #[actix_web::test]
+    async fn test_increment_contention_returns_503() {
+        // 尝试上限为 0 的数据库直接放弃事务，用于检查 DbError::Contention 对应的响应
+        let db = Arc::new(Database::temporary().unwrap().with_max_tx_attempts(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .app_data(web::Data::new(GlobalRateGate::new(0)))
+                .route("/api/increment", web::post().to(increment_week)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/api/increment").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "1");
+    }
+
+    #[actix_web::test]
+    async fn test_limits_reflect_config() {
+        let mut config = AppConfig::for_tests();
+        config.button_min_interval_ms = 250;
+        config.max_body_bytes = 1024;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .route("/api/limits", web::get().to(get_limits)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/limits").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["button_min_interval_ms"], 250);
+        assert_eq!(body["max_body_bytes"], 1024);
+        assert_eq!(body["max_history_limit"], MAX_HISTORY_LIMIT);
+    }
+
+    #[actix_web::test]
+    async fn test_badge_conditional_get() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .route("/api/badge.svg", web::get().to(get_badge)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/badge.svg").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("Cache-Control").is_some());
+        let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+
+        let conditional = || {
+            test::TestRequest::get()
+                .uri("/api/badge.svg")
+                .insert_header(("If-None-Match", etag.clone()))
+                .to_request()
+        };
+        let resp = test::call_service(&app, conditional()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        // 周数变化后 ETag 随之变化
+        db.increment_week(None).await.unwrap();
+        let resp = test::call_service(&app, conditional()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_vs_previous_season() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .route("/api/stats/vs-previous", web::get().to(get_vs_previous)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/api/stats/vs-previous").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let season = PreviousSeason::from_json(
+            r#"[{"time": "2023-01-01T00:00:00Z", "week_count": 5}]"#,
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(season))
+                .route("/api/stats/vs-previous", web::get().to(get_vs_previous)),
+        )
+        .await;
+        db.increment_week(None).await.unwrap();
+        let req = test::TestRequest::get().uri("/api/stats/vs-previous").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["available"], true);
+        assert_eq!(body["previous_week_count"], 5);
+        assert_eq!(body["difference"], -4);
+    }
+
+    #[actix_web::test]
+    async fn test_vs_previous_season_uses_database_clock() {
+        use chrono::TimeZone;
+
+        let clock = Arc::new(clock::ManualClock::new(
+            chrono::Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        ));
+        let db = Arc::new(Database::temporary().unwrap().with_clock(clock.clone()));
+        db.increment_week(None).await.unwrap();
+        let season = PreviousSeason::from_json(
+            r#"[{"time": "2023-01-01T00:00:00Z", "week_count": 5},
+                {"time": "2023-01-03T00:00:00Z", "week_count": 9}]"#,
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(season))
+                .route("/api/stats/vs-previous", web::get().to(get_vs_previous)),
+        )
+        .await;
+        let request = || test::TestRequest::get().uri("/api/stats/vs-previous").to_request();
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body["elapsed_secs"], 0);
+        assert_eq!(body["previous_week_count"], 5);
+
+        // 本季开始后两天，与上一季的第二个点对比
+        clock.set(chrono::Utc.with_ymd_and_hms(2024, 1, 3, 0, 0, 0).unwrap());
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body["elapsed_secs"], 2 * 24 * 60 * 60);
+        assert_eq!(body["previous_week_count"], 9);
+        assert_eq!(body["difference"], -8);
+
+        // 删除最早的历史记录不会移动本季开始时间
+        for (key, _) in db.list_history(10, None).await.unwrap() {
+            let key = db::parse_history_key(&key).unwrap();
+            db.delete_history(key, false).await.unwrap();
+        }
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body["elapsed_secs"], 2 * 24 * 60 * 60);
+        assert_eq!(body["previous_week_count"], 9);
+    }
+
+    #[actix_web::test]
+    async fn test_manifest_uses_branding() {
+        let mut config = AppConfig::for_tests();
+        config.site_title = "测试活动 开放倒计时".to_string();
+        config.accent_color = "#ff0000".to_string();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .route("/manifest.json", web::get().to(manifest)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/manifest.json").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/manifest+json"
+        );
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["name"], "测试活动 开放倒计时");
+        assert_eq!(body["theme_color"], "#ff0000");
+    }
+
+    #[actix_web::test]
+    async fn test_get_data_jsonp() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .route("/api/data", web::get().to(get_data)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/data?callback=onData")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/javascript; charset=utf-8"
+        );
+        let body = test::read_body(resp).await;
+        assert_eq!(body, r#"/**/onData({"success":true,"week_count":1,"just_counted":true});"#);
+
+        // 非法回调函数名被拒绝
+        let req = test::TestRequest::get()
+            .uri("/api/data?callback=alert(1)")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // 未提供回调时仍为普通 JSON；同一 IP 再次请求不再计数
+        let req = test::TestRequest::get().uri("/api/data").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), "application/json");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["just_counted"], false);
+    }
+
+    #[actix_web::test]
+    async fn test_initial_flag_only_when_enabled() {
+        let mut config = AppConfig::for_tests();
+        config.mark_initial = false;
+        config.initial_message = None;
+        let body = serde_json::to_value(ApiResponse::current(0, &config)).unwrap();
+        assert_eq!(body, serde_json::json!({"success": true, "week_count": 0}));
+
+        config.mark_initial = true;
+        config.initial_message = Some("尚未开始".to_string());
+        let body = serde_json::to_value(ApiResponse::current(0, &config)).unwrap();
+        assert_eq!(body["is_initial"], true);
+        assert_eq!(body["message"], "尚未开始");
+
+        // 周数不为 0 时不附带
+        let body = serde_json::to_value(ApiResponse::current(3, &config)).unwrap();
+        assert_eq!(body, serde_json::json!({"success": true, "week_count": 3}));
+    }
+
+    #[actix_web::test]
+    async fn test_increment_idempotency_key() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .app_data(web::Data::new(GlobalRateGate::new(0)))
+                .route("/api/increment", web::post().to(increment_week)),
+        )
+        .await;
+
+        let request = || {
+            test::TestRequest::post()
+                .uri("/api/increment")
+                .insert_header(("Idempotency-Key", "retry-1"))
+                .to_request()
+        };
+        let resp = test::call_service(&app, request()).await;
+        assert!(resp.headers().get("Idempotent-Replayed").is_none());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["week_count"], 1);
+
+        // 重试不再增加
+        let resp = test::call_service(&app, request()).await;
+        assert_eq!(resp.headers().get("Idempotent-Replayed").unwrap(), "true");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["week_count"], 1);
+
+        let req = test::TestRequest::post()
+            .uri("/api/increment")
+            .insert_header(("Idempotency-Key", "  "))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_visit_counts_once_per_day() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .route("/api/visit", web::post().to(visit)),
+        )
+        .await;
+
+        let request = || {
+            test::TestRequest::post()
+                .uri("/api/visit")
+                .peer_addr("10.0.0.1:1234".parse().unwrap())
+                .to_request()
+        };
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body, serde_json::json!({"counted": true, "week_count": 1}));
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body, serde_json::json!({"counted": false, "week_count": 1}));
+
+        let req = test::TestRequest::post().uri("/api/visit?src=qr%20code").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_increment_global_rate_gate() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .app_data(web::Data::new(GlobalRateGate::new(60_000)))
+                .route("/api/increment", web::post().to(increment_week)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/api/increment").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post().uri("/api/increment").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key("Retry-After"));
+    }
+
+    #[actix_web::test]
+    async fn test_idempotent_replay_bypasses_rate_gate() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .app_data(web::Data::new(GlobalRateGate::new(60_000)))
+                .route("/api/increment", web::post().to(increment_week)),
+        )
+        .await;
+
+        let increment = |key: &str| {
+            test::TestRequest::post()
+                .uri("/api/increment")
+                .insert_header(("Idempotency-Key", key.to_string()))
+                .to_request()
+        };
+        assert_eq!(test::call_service(&app, increment("a")).await.status(), StatusCode::OK);
+
+        // 间隔内的重试返回首次结果
+        let resp = test::call_service(&app, increment("a")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Idempotent-Replayed").unwrap(), "true");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["week_count"], 1);
+
+        // 新的键仍受频率限制
+        let resp = test::call_service(&app, increment("b")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(db.get_week_count().await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_failed_increment_releases_rate_gate() {
+        let db = Arc::new(Database::temporary().unwrap().with_max_tx_attempts(0));
+        let gate = web::Data::new(GlobalRateGate::new(60_000));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .app_data(gate.clone())
+                .route("/api/increment", web::post().to(increment_week)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/api/increment").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        // 失败的增加没有占用放行
+        assert!(gate.try_acquire().is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_head_me_already_counted() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .route("/api/me", web::head().to(head_me)),
+        )
+        .await;
+
+        let req = test::TestRequest::default()
+            .method(actix_web::http::Method::HEAD)
+            .uri("/api/me")
+            .insert_header(("X-Real-IP", "1.2.3.4"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("X-Already-Counted").unwrap(), "false");
+
+        db.increment_week_with_ip_check("1.2.3.4".to_string(), None, None).await.unwrap();
+
+        let req = test::TestRequest::default()
+            .method(actix_web::http::Method::HEAD)
+            .uri("/api/me")
+            .insert_header(("X-Real-IP", "1.2.3.4"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("X-Already-Counted").unwrap(), "true");
+        assert!(test::read_body(resp).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_versioned_and_legacy_routes() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .wrap(middleware::DefaultHeaders::new().add(("X-API-Version", API_VERSION)))
+                .service(api_scope("/api/v1", &AppConfig::for_tests()))
+                .service(api_scope("/api", &AppConfig::for_tests())),
+        )
+        .await;
+
+        for uri in ["/api/v1/date", "/api/date"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(resp.headers().get("X-API-Version").unwrap(), API_VERSION);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_disable_button_unregisters_increment() {
+        let mut config = AppConfig::for_tests();
+        config.disable_button = true;
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(GlobalRateGate::new(0)))
+                .route("/", web::get().to(index))
+                .service(api_scope("/api", &config)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/api/increment").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // 页面上的按钮同时隐藏
+        let req = test::TestRequest::get().uri("/").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let html = std::str::from_utf8(&body).unwrap();
+        assert!(html.contains(r#"<div class="button-container" hidden>"#));
+    }
+
+    #[actix_web::test]
+    async fn test_trailing_slash_is_trimmed() {
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(AppConfig::for_tests()))
+                .wrap(middleware::NormalizePath::trim())
+                .route("/", web::get().to(index))
+                .service(api_scope("/api/v1", &AppConfig::for_tests()))
+                .service(api_scope("/api", &AppConfig::for_tests()))
+                .route("/health", web::get().to(health_check)),
+        )
+        .await;
+
+        for uri in ["/", "/api/data/", "/api/v1/data/", "/api/date/", "/api/limits//", "/health/"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_forwarded_client_ip_trusted_hops() {
+        // 客户端 -> 代理一 -> 代理二 -> 本服务：代理二追加了代理一的地址
+        let chain = "6.6.6.6, 1.2.3.4, 10.0.0.1";
+
+        // 默认信任所有代理，取第一个条目（可能被客户端伪造）
+        assert_eq!(forwarded_client_ip(chain, TrustedHops::All), Some("6.6.6.6"));
+        // 信任一层代理：跳过最后一个条目，真实客户端是倒数第二个
+        assert_eq!(forwarded_client_ip(chain, TrustedHops::Count(1)), Some("1.2.3.4"));
+        assert_eq!(forwarded_client_ip(chain, TrustedHops::Count(0)), Some("10.0.0.1"));
+        // 条目不足时最左边的条目可能被伪造，不取任何条目
+        assert_eq!(forwarded_client_ip("1.2.3.4", TrustedHops::Count(2)), None);
+        assert_eq!(forwarded_client_ip("6.6.6.6, 10.0.0.1", TrustedHops::Count(2)), None);
+    }
+
+    #[actix_web::test]
+    async fn test_forwarded_client_ip_skips_empty_entries() {
+        // 末尾的空条目不计为可信代理
+        assert_eq!(forwarded_client_ip("1.2.3.4, ", TrustedHops::Count(0)), Some("1.2.3.4"));
+        assert_eq!(forwarded_client_ip("6.6.6.6, 1.2.3.4, ,", TrustedHops::Count(1)), Some("6.6.6.6"));
+        assert_eq!(forwarded_client_ip(" , ", TrustedHops::Count(0)), None);
+    }
+
+    #[actix_web::test]
+    async fn test_get_client_ip_short_chain_falls_back_to_peer() {
+        let req = test::TestRequest::default()
+            .peer_addr("9.9.9.9:1234".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "6.6.6.6"))
+            .to_http_request();
+        let connection_info = req.connection_info().clone();
+        assert_eq!(get_client_ip(&req, &connection_info, TrustedHops::Count(1)), "9.9.9.9");
+    }
+
+    #[actix_web::test]
+    async fn test_get_client_ip_two_proxy_chain() {
+        let req = test::TestRequest::default()
+            .insert_header(("X-Forwarded-For", "1.2.3.4, 10.0.0.1"))
+            .to_http_request();
+        let connection_info = req.connection_info().clone();
+        assert_eq!(
+            get_client_ip(&req, &connection_info, TrustedHops::Count(1)),
+            "1.2.3.4"
+        );
+    }
+
+    /// 按给定请求头构造请求并取客户端 IP，远程地址为 9.9.9.9
+    fn client_ip_with(headers: &[(&str, &str)]) -> String {
+        let mut req = test::TestRequest::default().peer_addr("9.9.9.9:1234".parse().unwrap());
+        for &(name, value) in headers {
+            req = req.insert_header((name, value));
+        }
+        let req = req.to_http_request();
+        let connection_info = req.connection_info().clone();
+        get_client_ip(&req, &connection_info, TrustedHops::All)
+    }
+
+    #[actix_web::test]
+    async fn test_get_client_ip_empty_forwarded_for_falls_through() {
+        let real_ip = [("X-Real-IP", "2.2.2.2")];
+        for empty in ["", "   ", ",", " , 1.1.1.1"] {
+            let headers = [("X-Forwarded-For", empty), real_ip[0]];
+            assert_eq!(client_ip_with(&headers), "2.2.2.2", "X-Forwarded-For: {:?}", empty);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_get_client_ip_empty_real_ip_falls_through() {
+        for empty in ["", "  "] {
+            let headers = [
+                ("X-Forwarded-For", ""),
+                ("X-Real-IP", empty),
+                ("CF-Connecting-IP", "3.3.3.3"),
+            ];
+            assert_eq!(client_ip_with(&headers), "3.3.3.3", "X-Real-IP: {:?}", empty);
+        }
+        // 有效值两端的空白被去掉
+        assert_eq!(client_ip_with(&[("X-Real-IP", " 2.2.2.2 ")]), "2.2.2.2");
+    }
+
+    #[actix_web::test]
+    async fn test_get_client_ip_empty_cf_connecting_ip_falls_back_to_peer() {
+        for empty in ["", "  "] {
+            let headers = [("X-Forwarded-For", ","), ("X-Real-IP", ""), ("CF-Connecting-IP", empty)];
+            assert_eq!(client_ip_with(&headers), "9.9.9.9", "CF-Connecting-IP: {:?}", empty);
+        }
+        assert_eq!(client_ip_with(&[]), "9.9.9.9");
+    }
+
+    #[actix_web::test]
+    async fn test_runtime_requires_admin_token() {
+        let mut config = AppConfig::for_tests();
+        config.admin_token = Some("secret".to_string());
+        let db = Arc::new(Database::temporary().unwrap().with_max_blocking_ops(8));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(HttpWorkers(3)))
+                .route("/api/debug/runtime", web::get().to(get_runtime)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/debug/runtime").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::get()
+            .uri("/api/debug/runtime")
+            .insert_header(("X-Admin-Token", "secret"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["blocking"]["max_blocking_ops"], 8);
+        assert_eq!(body["blocking"]["available_permits"], 8);
+        assert_eq!(body["blocking"]["in_flight"], 0);
+        assert_eq!(body["http_workers"], 3);
+        assert!(body["current_worker_runtime"]["num_workers"].as_u64().unwrap() >= 1);
+    }
+
+    #[actix_web::test]
+    async fn test_undo_reset_returns_410_after_grace_period() {
+        use chrono::TimeZone;
+
+        let mut config = AppConfig::for_tests();
+        config.admin_token = Some("secret".to_string());
+        config.reset_undo_grace_secs = 60;
+        let clock = Arc::new(clock::ManualClock::new(
+            chrono::Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap(),
+        ));
+        let db = Arc::new(Database::temporary().unwrap().with_clock(clock.clone()));
+        db.increment_week(None).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(config))
+                .route("/api/reset", web::post().to(reset_weeks))
+                .route("/api/reset/undo", web::post().to(undo_reset)),
+        )
+        .await;
+        let admin_post = |uri| {
+            test::TestRequest::post()
+                .uri(uri)
+                .insert_header(("X-Admin-Token", "secret"))
+                .to_request()
+        };
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, admin_post("/api/reset")).await;
+        assert_eq!(body["previous_count"], 1);
+        // 撤销期限使用数据库时钟
+        assert_eq!(body["undo_deadline"], "2024-03-01T08:01:00+00:00");
+
+        clock.set(chrono::Utc.with_ymd_and_hms(2024, 3, 1, 8, 2, 0).unwrap());
+        let resp = test::call_service(&app, admin_post("/api/reset/undo")).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+        assert_eq!(db.get_week_count().await.unwrap(), 0);
+
+        // 新的重置在期限内可以撤销
+        test::call_service(&app, admin_post("/api/reset")).await;
+        let body: serde_json::Value = test::call_and_read_body_json(&app, admin_post("/api/reset/undo")).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["week_count"], 0);
+        let resp = test::call_service(&app, admin_post("/api/reset/undo")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_weighted_click_after_page_view_counts_full() {
+        let mut config = AppConfig::for_tests();
+        config.weighted_increments = true;
+        let db = Arc::new(Database::temporary().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.clone()))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(GlobalRateGate::new(0)))
+                .route("/api/visit", web::post().to(visit))
+                .route("/api/increment", web::post().to(increment_week)),
+        )
+        .await;
+        let request = |uri| {
+            test::TestRequest::post()
+                .uri(uri)
+                .peer_addr("10.0.0.1:1234".parse().unwrap())
+                .insert_header(("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) Firefox/128.0"))
+                .to_request()
+        };
+
+        // 先浏览首页再点击按钮，点击按完整权重计数
+        test::call_service(&app, request("/api/visit")).await;
+        test::call_service(&app, request("/api/increment")).await;
+        let history = db.list_history(10, None).await.unwrap();
+        assert_eq!(history[0].1.source, "button");
+        assert_eq!(history[0].1.units, db::COUNT_UNIT);
+
+        // 同一 IP 当天再次点击才按重复计数
+        test::call_service(&app, request("/api/increment")).await;
+        let history = db.list_history(10, None).await.unwrap();
+        assert_eq!(history[0].1.units, db::weight_units(trust::REPEAT_IP_WEIGHT));
+    }
 }
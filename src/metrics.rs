@@ -0,0 +1,164 @@
+use std::fmt::Write;
+
+use crate::db::MetricsSnapshot;
+
+/// Prometheus 文本格式的 Content-Type
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// OpenMetrics 文本格式的 Content-Type
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// 指标类型
+#[derive(Clone, Copy)]
+enum MetricType {
+    Gauge,
+    Counter,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Gauge => "gauge",
+            MetricType::Counter => "counter",
+        }
+    }
+}
+
+/// 单个指标
+struct Metric {
+    /// 指标族名称（OpenMetrics 中计数器样本会追加 `_total`）
+    name: &'static str,
+    help: &'static str,
+    metric_type: MetricType,
+    unit: Option<&'static str>,
+    value: f64,
+}
+
+/// 两种格式共用的指标集合
+fn collect(snapshot: &MetricsSnapshot) -> Vec<Metric> {
+    let mut metrics = vec![
+        Metric {
+            name: "teacon_week_count",
+            help: "Current week count.",
+            metric_type: MetricType::Gauge,
+            unit: None,
+            value: snapshot.week_count as f64,
+        },
+        Metric {
+            name: "teacon_unique_ips",
+            help: "Number of distinct IPs that have counted a visit.",
+            metric_type: MetricType::Gauge,
+            unit: None,
+            value: snapshot.unique_ips as f64,
+        },
+        Metric {
+            name: "teacon_increments",
+            help: "Total number of increments ever recorded; never decreases when history is deleted or restored.",
+            metric_type: MetricType::Counter,
+            unit: None,
+            value: snapshot.increments_total as f64,
+        },
+        Metric {
+            name: "teacon_increments_today",
+            help: "Number of increments during the current UTC day.",
+            metric_type: MetricType::Gauge,
+            unit: None,
+            value: snapshot.increments_today as f64,
+        },
+    ];
+
+    if let Some(time) = snapshot.last_increment_time {
+        metrics.push(Metric {
+            name: "teacon_last_increment_timestamp_seconds",
+            help: "Unix timestamp of the most recent increment.",
+            metric_type: MetricType::Gauge,
+            unit: Some("seconds"),
+            value: time.timestamp_millis() as f64 / 1000.0,
+        });
+    }
+
+    metrics
+}
+
+/// 以 Prometheus 文本格式输出指标
+pub fn render_prometheus(snapshot: &MetricsSnapshot) -> String {
+    let mut output = String::new();
+    for metric in collect(snapshot) {
+        // Prometheus 格式中计数器名称本身带 `_total` 后缀
+        let name = match metric.metric_type {
+            MetricType::Counter => format!("{}_total", metric.name),
+            MetricType::Gauge => metric.name.to_string(),
+        };
+        let _ = writeln!(output, "# HELP {} {}", name, metric.help);
+        let _ = writeln!(output, "# TYPE {} {}", name, metric.metric_type.as_str());
+        let _ = writeln!(output, "{} {}", name, metric.value);
+    }
+    output
+}
+
+/// 以 OpenMetrics 文本格式输出指标（以 `# EOF` 结尾）
+pub fn render_openmetrics(snapshot: &MetricsSnapshot) -> String {
+    let mut output = String::new();
+    for metric in collect(snapshot) {
+        let _ = writeln!(output, "# TYPE {} {}", metric.name, metric.metric_type.as_str());
+        if let Some(unit) = metric.unit {
+            let _ = writeln!(output, "# UNIT {} {}", metric.name, unit);
+        }
+        let _ = writeln!(output, "# HELP {} {}", metric.name, metric.help);
+        let sample = match metric.metric_type {
+            MetricType::Counter => format!("{}_total", metric.name),
+            MetricType::Gauge => metric.name.to_string(),
+        };
+        let _ = writeln!(output, "{} {}", sample, metric.value);
+    }
+    output.push_str("# EOF\n");
+    output
+}
+
+/// 根据 Accept 头判断客户端是否需要 OpenMetrics 格式
+pub fn wants_openmetrics(accept: &str) -> bool {
+    accept
+        .split(',')
+        .any(|item| item.trim().starts_with("application/openmetrics-text"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn snapshot() -> MetricsSnapshot {
+        MetricsSnapshot {
+            week_count: 42,
+            unique_ips: 5,
+            increments_total: 40,
+            increments_today: 3,
+            last_increment_time: Some(Utc.timestamp_opt(1704067200, 0).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let output = render_prometheus(&snapshot());
+        assert!(output.contains("# TYPE teacon_week_count gauge\nteacon_week_count 42\n"));
+        assert!(output.contains("# TYPE teacon_increments_total counter\nteacon_increments_total 40\n"));
+        assert!(!output.contains("# EOF"));
+        assert!(!output.contains("# UNIT"));
+    }
+
+    #[test]
+    fn test_render_openmetrics() {
+        let output = render_openmetrics(&snapshot());
+        assert!(output.contains("# TYPE teacon_increments counter\n"));
+        assert!(output.contains("teacon_increments_total 40\n"));
+        assert!(output.contains("# UNIT teacon_last_increment_timestamp_seconds seconds\n"));
+        assert!(output.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_wants_openmetrics() {
+        assert!(wants_openmetrics("application/openmetrics-text; version=1.0.0,text/plain;q=0.5"));
+        assert!(!wants_openmetrics("text/plain"));
+        assert!(!wants_openmetrics("*/*"));
+    }
+}
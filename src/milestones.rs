@@ -0,0 +1,73 @@
+/// 里程碑列表（升序、去重），来自 `MILESTONES` 配置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Milestones(Vec<u64>);
+
+impl Milestones {
+    pub fn new(mut values: Vec<u64>) -> Self {
+        values.sort_unstable();
+        values.dedup();
+        Milestones(values)
+    }
+
+    /// 解析逗号分隔的里程碑列表，忽略无效项
+    pub fn parse(value: &str) -> Self {
+        let values = value
+            .split(',')
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .filter_map(|item| match item.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    log::warn!("忽略无效的里程碑: {}", item);
+                    None
+                }
+            })
+            .collect();
+        Self::new(values)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// 周数从 `previous` 增加到 `current` 时跨过（`previous < m <= current`）的里程碑
+    pub fn crossed(&self, previous: u64, current: u64) -> impl Iterator<Item = u64> + '_ {
+        self.0
+            .iter()
+            .copied()
+            .filter(move |&milestone| previous < milestone && milestone <= current)
+    }
+
+    /// 当前周数之上的下一个里程碑，已全部达成时返回 None
+    pub fn next_after(&self, week_count: u64) -> Option<u64> {
+        self.0.iter().copied().find(|&milestone| milestone > week_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sorts_and_dedups() {
+        assert_eq!(Milestones::parse("500, 100,abc,100,"), Milestones::new(vec![100, 500]));
+        assert!(Milestones::parse("").is_empty());
+    }
+
+    #[test]
+    fn test_next_after() {
+        let milestones = Milestones::new(vec![100, 500]);
+        assert_eq!(milestones.next_after(0), Some(100));
+        assert_eq!(milestones.next_after(100), Some(500));
+        assert_eq!(milestones.next_after(458), Some(500));
+        assert_eq!(milestones.next_after(500), None);
+    }
+
+    #[test]
+    fn test_crossed() {
+        let milestones = Milestones::new(vec![100, 500]);
+        assert_eq!(milestones.crossed(99, 100).collect::<Vec<_>>(), vec![100]);
+        assert_eq!(milestones.crossed(100, 101).count(), 0);
+        assert_eq!(milestones.crossed(0, 500).collect::<Vec<_>>(), vec![100, 500]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::{Database, MetricsSnapshot};
+use crate::metrics;
+
+/// 推送使用的 Content-Type
+pub const CONTENT_TYPE: &str = metrics::PROMETHEUS_CONTENT_TYPE;
+
+/// 生成 Pushgateway 推送内容
+/// Pushgateway 只接受 Prometheus 文本格式，不接受 OpenMetrics 的 `# EOF` 结尾
+pub fn payload(snapshot: &MetricsSnapshot) -> String {
+    metrics::render_prometheus(snapshot)
+}
+
+/// 拼接推送地址 `<base>/metrics/job/<job>`，任务名按路径段转义
+pub fn push_url(base: &str, job: &str) -> Result<reqwest::Url, String> {
+    let mut url = reqwest::Url::parse(base).map_err(|e| format!("无效的 Pushgateway 地址 {}: {}", base, e))?;
+    url.path_segments_mut()
+        .map_err(|_| format!("无效的 Pushgateway 地址 {}", base))?
+        .pop_if_empty()
+        .extend(["metrics", "job", job]);
+    Ok(url)
+}
+
+/// 定期将指标推送到 Pushgateway 的客户端
+pub struct Pusher {
+    client: reqwest::Client,
+    url: reqwest::Url,
+}
+
+impl Pusher {
+    /// 推送请求超时时间
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(base: &str, job: &str) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Self::TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Ok(Pusher {
+            client,
+            url: push_url(base, job)?,
+        })
+    }
+
+    /// 推送一次指标（PUT 会替换该任务分组下的全部指标）
+    pub async fn push(&self, snapshot: &MetricsSnapshot) -> Result<(), String> {
+        let response = self
+            .client
+            .put(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(payload(snapshot))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Pushgateway 返回非成功状态码: {}", response.status()))
+        }
+    }
+
+    /// 按固定间隔推送，失败只记录日志，不影响服务
+    pub async fn run(self, db: Arc<Database>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let snapshot = match db.metrics_snapshot().await {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    log::warn!("收集推送指标失败: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.push(&snapshot).await {
+                log::warn!("推送指标到 {} 失败: {}", self.url, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_url() {
+        assert_eq!(
+            push_url("http://pushgateway:9091", "teacon").unwrap().as_str(),
+            "http://pushgateway:9091/metrics/job/teacon"
+        );
+        assert_eq!(
+            push_url("http://pushgateway:9091/prefix/", "a b/c").unwrap().as_str(),
+            "http://pushgateway:9091/prefix/metrics/job/a%20b%2Fc"
+        );
+        assert!(push_url("not a url", "teacon").is_err());
+    }
+
+    #[test]
+    fn test_payload_has_no_eof() {
+        let snapshot = MetricsSnapshot {
+            week_count: 3,
+            unique_ips: 2,
+            increments_total: 3,
+            increments_today: 1,
+            last_increment_time: None,
+        };
+        let output = payload(&snapshot);
+        assert!(output.contains("teacon_week_count 3\n"));
+        assert!(output.contains("teacon_unique_ips 2\n"));
+        assert!(output.contains("teacon_increments_today 1\n"));
+        assert!(!output.contains("# EOF"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 全局增加频率闸门：限制整个服务器上两次按钮增加之间的最小间隔
+/// 用原子变量记录上一次放行的时间（毫秒），无需加锁；间隔为 0 时不做限制
+pub struct GlobalRateGate {
+    min_interval_ms: u64,
+    last_allowed_ms: AtomicU64,
+}
+
+impl GlobalRateGate {
+    pub fn new(min_interval_ms: u64) -> Self {
+        GlobalRateGate {
+            min_interval_ms,
+            last_allowed_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// 尝试放行一次增加；距上次放行不足最小间隔时返回 Err(剩余等待毫秒数)
+    /// 放行后增加没有提交时，应通过 [`GlobalRateGate::release`] 归还
+    pub fn try_acquire(&self) -> Result<RateSlot, u64> {
+        self.try_acquire_at(now_ms())
+    }
+
+    fn try_acquire_at(&self, now_ms: u64) -> Result<RateSlot, u64> {
+        if self.min_interval_ms == 0 {
+            return Ok(RateSlot {
+                previous_ms: 0,
+                acquired_ms: 0,
+            });
+        }
+
+        let mut last = self.last_allowed_ms.load(Ordering::Acquire);
+        loop {
+            let elapsed = now_ms.saturating_sub(last);
+            if last != 0 && elapsed < self.min_interval_ms {
+                return Err(self.min_interval_ms - elapsed);
+            }
+
+            // 并发请求中只有一个能成功更新时间戳
+            match self.last_allowed_ms.compare_exchange_weak(
+                last,
+                now_ms,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Ok(RateSlot {
+                        previous_ms: last,
+                        acquired_ms: now_ms,
+                    })
+                }
+                Err(current) => last = current,
+            }
+        }
+    }
+
+    /// 归还未使用的放行，恢复上一次放行的时间；期间已有其他请求被放行时不做修改
+    pub fn release(&self, slot: RateSlot) {
+        if self.min_interval_ms == 0 {
+            return;
+        }
+        let _ = self.last_allowed_ms.compare_exchange(
+            slot.acquired_ms,
+            slot.previous_ms,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+}
+
+/// 一次放行，记录放行时间和之前的放行时间，用于归还
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateSlot {
+    previous_ms: u64,
+    acquired_ms: u64,
+}
+
+/// 当前 Unix 时间（毫秒）
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_gate_always_allows() {
+        let gate = GlobalRateGate::new(0);
+        assert!(gate.try_acquire_at(1000).is_ok());
+        assert!(gate.try_acquire_at(1000).is_ok());
+    }
+
+    #[test]
+    fn test_gate_enforces_interval() {
+        let gate = GlobalRateGate::new(500);
+        assert!(gate.try_acquire_at(10_000).is_ok());
+        assert_eq!(gate.try_acquire_at(10_200), Err(300));
+        // 被拒绝的请求不会推迟下一次放行
+        assert!(gate.try_acquire_at(10_500).is_ok());
+        assert_eq!(gate.try_acquire_at(10_999), Err(1));
+    }
+
+    #[test]
+    fn test_released_slot_allows_next_request() {
+        let gate = GlobalRateGate::new(500);
+        assert!(gate.try_acquire_at(10_000).is_ok());
+        let slot = gate.try_acquire_at(10_600).unwrap();
+        gate.release(slot);
+        // 归还后恢复到上一次放行的时间
+        assert_eq!(gate.try_acquire_at(10_400), Err(100));
+        assert!(gate.try_acquire_at(10_600).is_ok());
+
+        // 首次放行归还后不再限制
+        let gate = GlobalRateGate::new(500);
+        let slot = gate.try_acquire_at(10_000).unwrap();
+        gate.release(slot);
+        assert!(gate.try_acquire_at(10_100).is_ok());
+    }
+}
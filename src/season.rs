@@ -0,0 +1,72 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+/// 上一季时间序列中的一个点，字段与历史记录相同，其他字段会被忽略
+#[derive(Deserialize)]
+struct SeasonPoint {
+    time: DateTime<Utc>,
+    week_count: u64,
+}
+
+/// 上一季的周数时间序列，用于与本季同一时间点对比
+#[derive(Debug, Clone)]
+pub struct PreviousSeason {
+    /// 按距离上一季开始时间的偏移排序的 (偏移, 周数)
+    points: Vec<(Duration, u64)>,
+}
+
+impl PreviousSeason {
+    /// 从 JSON 数组解析（如 `/api/history` 返回的 `entries`），以最早的点作为上一季的开始
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let mut points: Vec<SeasonPoint> = serde_json::from_str(json)?;
+        points.sort_by_key(|point| point.time);
+        let start = points.first().map(|point| point.time).unwrap_or_default();
+        Ok(PreviousSeason {
+            points: points
+                .into_iter()
+                .map(|point| (point.time - start, point.week_count))
+                .collect(),
+        })
+    }
+
+    /// 从文件加载
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_json(&json).map_err(|e| e.to_string())
+    }
+
+    /// 上一季在开始后经过 `elapsed` 时的周数（不晚于该偏移的最后一个点，之前没有点时为 0）
+    pub fn count_at(&self, elapsed: Duration) -> u64 {
+        let index = self.points.partition_point(|(offset, _)| *offset <= elapsed);
+        index.checked_sub(1).map_or(0, |i| self.points[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_at_elapsed_offset() {
+        let season = PreviousSeason::from_json(
+            r#"[
+                {"time": "2023-01-01T02:00:00Z", "week_count": 3, "ip": null},
+                {"time": "2023-01-01T00:00:00Z", "week_count": 1},
+                {"time": "2023-01-01T01:00:00Z", "week_count": 2}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(season.count_at(Duration::zero()), 1);
+        assert_eq!(season.count_at(Duration::minutes(90)), 2);
+        assert_eq!(season.count_at(Duration::hours(10)), 3);
+        assert_eq!(season.count_at(Duration::seconds(-1)), 0);
+    }
+
+    #[test]
+    fn test_empty_season() {
+        let season = PreviousSeason::from_json("[]").unwrap();
+        assert_eq!(season.count_at(Duration::hours(1)), 0);
+        assert!(PreviousSeason::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::stream::{self, Stream};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::db::IncrementOutcome;
+use crate::hooks::IncrementHook;
+use crate::milestones::Milestones;
+
+/// 事件通道容量，订阅者落后超过该数量时跳过旧事件
+const CHANNEL_CAPACITY: usize = 64;
+
+/// SSE 心跳注释，客户端会忽略注释行，但代理会因此保持连接
+const KEEPALIVE: &[u8] = b": keepalive\n\n";
+
+/// 周数变化广播器，作为增加周数钩子注册后向所有 SSE 订阅者推送新周数
+#[derive(Clone)]
+pub struct EventBroadcaster {
+    sender: broadcast::Sender<u64>,
+}
+
+impl EventBroadcaster {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        EventBroadcaster { sender }
+    }
+
+    /// 订阅周数变化
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBroadcaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl IncrementHook for EventBroadcaster {
+    fn name(&self) -> &str {
+        "sse"
+    }
+
+    async fn on_increment(&self, outcome: &IncrementOutcome) {
+        // 没有订阅者时发送失败属于正常情况
+        let _ = self.sender.send(outcome.week_count);
+    }
+}
+
+/// 里程碑广播器，作为增加周数钩子注册后只在周数跨过配置的里程碑时推送该里程碑
+#[derive(Clone)]
+pub struct MilestoneBroadcaster {
+    milestones: Milestones,
+    sender: broadcast::Sender<u64>,
+}
+
+impl MilestoneBroadcaster {
+    pub fn new(milestones: Milestones) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        MilestoneBroadcaster { milestones, sender }
+    }
+
+    /// 订阅里程碑事件
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.sender.subscribe()
+    }
+}
+
+#[async_trait]
+impl IncrementHook for MilestoneBroadcaster {
+    fn name(&self) -> &str {
+        "sse_milestones"
+    }
+
+    /// 周数每次从低于里程碑增加到不低于它时推送：修正或撤销重置一次跨过的多个里程碑都会推送，
+    /// 重置后周数重新累计，再次到达的里程碑会再次推送
+    async fn on_increment(&self, outcome: &IncrementOutcome) {
+        for milestone in self.milestones.crossed(outcome.previous_count, outcome.week_count) {
+            let _ = self.sender.send(milestone);
+        }
+    }
+}
+
+/// 格式化一条周数事件
+fn count_event(week_count: u64) -> Bytes {
+    Bytes::from(format!(
+        "event: count\ndata: {{\"week_count\":{}}}\n\n",
+        week_count
+    ))
+}
+
+/// 格式化一条里程碑事件
+fn milestone_event(milestone: u64) -> Bytes {
+    Bytes::from(format!(
+        "event: milestone\ndata: {{\"milestone\":{}}}\n\n",
+        milestone
+    ))
+}
+
+/// 将广播通道中的值按 `format` 格式化为 SSE 事件
+/// 超过 `heartbeat` 没有事件时发送一条心跳注释
+fn broadcast_events(
+    receiver: broadcast::Receiver<u64>,
+    heartbeat: Duration,
+    format: fn(u64) -> Bytes,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match tokio::time::timeout(heartbeat, receiver.recv()).await {
+                Ok(Ok(value)) => return Some((Ok(format(value)), receiver)),
+                // 落后时跳过旧事件，下一次接收会拿到较新的值
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return None,
+                Err(_) => return Some((Ok(Bytes::from_static(KEEPALIVE)), receiver)),
+            }
+        }
+    })
+}
+
+/// 构造 SSE 事件流：先发送当前周数，之后推送每次变化
+/// 超过 `heartbeat` 没有事件时发送一条心跳注释
+pub fn event_stream(
+    receiver: broadcast::Receiver<u64>,
+    initial: u64,
+    heartbeat: Duration,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let first = stream::once(async move { Ok(count_event(initial)) });
+    stream::StreamExt::chain(first, broadcast_events(receiver, heartbeat, count_event))
+}
+
+/// 构造里程碑 SSE 事件流：连接后不发送任何事件，只在跨过里程碑时推送
+/// 超过 `heartbeat` 没有事件时发送一条心跳注释
+pub fn milestone_stream(
+    receiver: broadcast::Receiver<u64>,
+    heartbeat: Duration,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    broadcast_events(receiver, heartbeat, milestone_event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_event_stream_sends_counts_and_keepalive() {
+        let broadcaster = EventBroadcaster::new();
+        let stream = event_stream(broadcaster.subscribe(), 3, Duration::from_millis(20));
+        let mut stream = Box::pin(stream);
+
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first, "event: count\ndata: {\"week_count\":3}\n\n");
+
+        // 没有事件时发送心跳注释
+        let heartbeat = stream.next().await.unwrap().unwrap();
+        assert_eq!(heartbeat, KEEPALIVE);
+
+        let outcome = IncrementOutcome {
+            previous_count: 3,
+            week_count: 4,
+            time: Utc::now(),
+            ip: None,
+        };
+        broadcaster.on_increment(&outcome).await;
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(update, "event: count\ndata: {\"week_count\":4}\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_milestone_stream_only_sends_crossed_milestones() {
+        let broadcaster = MilestoneBroadcaster::new(Milestones::new(vec![2, 4]));
+        let stream = milestone_stream(broadcaster.subscribe(), Duration::from_millis(20));
+        let mut stream = Box::pin(stream);
+
+        // 连接后保持安静，只有心跳
+        assert_eq!(stream.next().await.unwrap().unwrap(), KEEPALIVE);
+
+        for week_count in 1..=4 {
+            broadcaster.on_increment(&outcome(week_count - 1, week_count)).await;
+        }
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first, "event: milestone\ndata: {\"milestone\":2}\n\n");
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!(second, "event: milestone\ndata: {\"milestone\":4}\n\n");
+    }
+
+    fn outcome(previous_count: u64, week_count: u64) -> IncrementOutcome {
+        IncrementOutcome {
+            previous_count,
+            week_count,
+            time: Utc::now(),
+            ip: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_milestones_after_jump_and_reset() {
+        let broadcaster = MilestoneBroadcaster::new(Milestones::new(vec![2, 4, 10]));
+        let mut receiver = broadcaster.subscribe();
+
+        // 修正一次跨过多个里程碑
+        broadcaster.on_increment(&outcome(1, 5)).await;
+        assert_eq!(receiver.try_recv().unwrap(), 2);
+        assert_eq!(receiver.try_recv().unwrap(), 4);
+        assert!(receiver.try_recv().is_err());
+
+        // 重置后重新到达的里程碑再次推送
+        broadcaster.on_increment(&outcome(1, 2)).await;
+        assert_eq!(receiver.try_recv().unwrap(), 2);
+        assert!(receiver.try_recv().is_err());
+    }
+}
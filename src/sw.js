@@ -0,0 +1,37 @@
+// 极简 Service Worker：离线时显示最后一次获取到的周数
+const CACHE_NAME = 'teacon-counter-v1';
+
+self.addEventListener('install', () => {
+    self.skipWaiting();
+});
+
+self.addEventListener('activate', (event) => {
+    // 清理旧版本缓存
+    event.waitUntil(
+        caches.keys().then((keys) => Promise.all(
+            keys.filter((key) => key !== CACHE_NAME).map((key) => caches.delete(key))
+        )).then(() => self.clients.claim())
+    );
+});
+
+self.addEventListener('fetch', (event) => {
+    const url = new URL(event.request.url);
+    if (event.request.method !== 'GET' || url.origin !== self.location.origin) {
+        return;
+    }
+
+    // 首页和数据接口：优先网络，失败时回退到最后一次缓存的响应
+    if (url.pathname === '/' || url.pathname === '/api/data') {
+        event.respondWith(
+            fetch(event.request)
+                .then((response) => {
+                    if (response.ok) {
+                        const copy = response.clone();
+                        caches.open(CACHE_NAME).then((cache) => cache.put(url.pathname, copy));
+                    }
+                    return response;
+                })
+                .catch(() => caches.match(url.pathname))
+        );
+    }
+});
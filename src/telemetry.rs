@@ -0,0 +1,244 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use opentelemetry::metrics::{Counter, Histogram, MeterProvider};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{ExporterBuildError, Protocol, WithExportConfig};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::Resource;
+
+use crate::db::{Database, MetricsSnapshot};
+
+/// 从数据库刷新指标快照的间隔
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 导出请求超时时间
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 最近一次读取到的指标快照，指标回调在导出线程中同步读取
+type SharedSnapshot = Arc<RwLock<Option<MetricsSnapshot>>>;
+
+/// 启动 OTLP 指标导出
+/// 收集器地址、导出间隔等由 SDK 按标准的 `OTEL_EXPORTER_OTLP_*`、`OTEL_METRIC_EXPORT_*` 环境变量解析；
+/// 收集器不可达时导出失败只由 SDK 记录，不影响服务。返回的 provider 需要在退出前关闭以导出最后一批数据
+pub fn start(db: Arc<Database>) -> Result<SdkMeterProvider, ExporterBuildError> {
+    let exporter = opentelemetry_otlp::MetricExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_timeout(EXPORT_TIMEOUT)
+        .build()?;
+    let provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_attributes([KeyValue::new("service.name", "teacon-counter")])
+                .build(),
+        )
+        .build();
+
+    let snapshot: SharedSnapshot = Arc::default();
+    register_instruments(&provider, &snapshot);
+
+    // 导出回调不能等待数据库，由后台任务定期刷新快照
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            match db.metrics_snapshot().await {
+                Ok(latest) => *snapshot.write().unwrap() = Some(latest),
+                Err(e) => log::warn!("刷新 OpenTelemetry 指标失败: {}", e),
+            }
+        }
+    });
+
+    Ok(provider)
+}
+
+/// 注册与 `/metrics` 对应的可观测指标，还没有快照时不上报
+fn register_instruments(provider: &SdkMeterProvider, snapshot: &SharedSnapshot) {
+    let meter = provider.meter("teacon-counter");
+
+    let read = |field: fn(&MetricsSnapshot) -> u64| {
+        let snapshot = snapshot.clone();
+        move || snapshot.read().unwrap().as_ref().map(field)
+    };
+
+    let week_count = read(|s| s.week_count);
+    meter
+        .u64_observable_gauge("teacon.week_count")
+        .with_description("Current week count.")
+        .with_callback(move |observer| {
+            if let Some(value) = week_count() {
+                observer.observe(value, &[]);
+            }
+        })
+        .build();
+
+    let unique_ips = read(|s| s.unique_ips);
+    meter
+        .u64_observable_gauge("teacon.unique_ips")
+        .with_description("Number of distinct IPs that have counted a visit.")
+        .with_callback(move |observer| {
+            if let Some(value) = unique_ips() {
+                observer.observe(value, &[]);
+            }
+        })
+        .build();
+
+    // 单调递增的计数器，后端据此计算增加速率
+    let increments = read(|s| s.increments_total);
+    meter
+        .u64_observable_counter("teacon.increments")
+        .with_description("Total number of recorded increments.")
+        .with_callback(move |observer| {
+            if let Some(value) = increments() {
+                observer.observe(value, &[]);
+            }
+        })
+        .build();
+
+    let increments_today = read(|s| s.increments_today);
+    meter
+        .u64_observable_gauge("teacon.increments_today")
+        .with_description("Number of increments during the current UTC day.")
+        .with_callback(move |observer| {
+            if let Some(value) = increments_today() {
+                observer.observe(value, &[]);
+            }
+        })
+        .build();
+}
+
+/// HTTP 请求指标，按方法、路由模板和状态码分组
+#[derive(Clone)]
+pub struct HttpMetrics {
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl HttpMetrics {
+    pub fn new(provider: &SdkMeterProvider) -> Self {
+        let meter = provider.meter("teacon-counter");
+        HttpMetrics {
+            requests: meter
+                .u64_counter("http.server.request.count")
+                .with_description("Number of handled HTTP requests.")
+                .build(),
+            duration: meter
+                .f64_histogram("http.server.request.duration")
+                .with_description("Duration of handled HTTP requests.")
+                .with_unit("s")
+                .build(),
+        }
+    }
+
+    fn record(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let attributes = [
+            KeyValue::new("http.request.method", method.to_string()),
+            KeyValue::new("http.route", route.to_string()),
+            KeyValue::new("http.response.status_code", i64::from(status)),
+        ];
+        self.requests.add(1, &attributes);
+        self.duration.record(elapsed.as_secs_f64(), &attributes);
+    }
+}
+
+/// 记录每个请求的次数和耗时，未注册 `HttpMetrics` 时直接放行
+/// 路由使用匹配到的模板（如 `/api/history/{id}`），避免按具体路径产生过多分组
+pub async fn record_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req.app_data::<web::Data<HttpMetrics>>().cloned();
+    let started = Instant::now();
+    let res = next.call(req).await?;
+    if let Some(metrics) = metrics {
+        let route = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        metrics.record(
+            res.request().method().as_str(),
+            &route,
+            res.status().as_u16(),
+            started.elapsed(),
+        );
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpResponse};
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData, ResourceMetrics};
+    use opentelemetry_sdk::metrics::reader::MetricReader;
+    use opentelemetry_sdk::metrics::{InstrumentKind, ManualReader, Pipeline, Temporality};
+    use std::sync::Weak;
+
+    /// 让测试在 provider 持有 reader 后仍能手动收集
+    #[derive(Debug, Clone)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> OTelSdkResult {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            self.0.force_flush()
+        }
+
+        fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+            self.0.shutdown_with_timeout(timeout)
+        }
+
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_record_request_counts_by_route() {
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let provider = SdkMeterProvider::builder().with_reader(reader.clone()).build();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(HttpMetrics::new(&provider)))
+                .wrap(actix_web::middleware::from_fn(record_request))
+                .route("/items/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for path in ["/items/1", "/items/2"] {
+            let req = test::TestRequest::get().uri(path).to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let mut rm = ResourceMetrics::default();
+        reader.collect(&mut rm).unwrap();
+        let metrics: Vec<_> = rm.scope_metrics().flat_map(|scope| scope.metrics()).collect();
+        assert!(metrics.iter().any(|m| m.name() == "http.server.request.duration"));
+
+        // 两个具体路径归入同一个路由模板
+        let count = metrics
+            .iter()
+            .find(|m| m.name() == "http.server.request.count")
+            .unwrap();
+        let AggregatedMetrics::U64(MetricData::Sum(sum)) = count.data() else {
+            panic!("请求次数应为 u64 计数器");
+        };
+        let points: Vec<_> = sum.data_points().collect();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].value(), 2);
+        assert!(points[0]
+            .attributes()
+            .any(|kv| kv.key.as_str() == "http.route" && kv.value.as_str() == "/items/{id}"));
+    }
+}
@@ -0,0 +1,73 @@
+/// 极简模板替换：将 `{{name}}` 占位符替换为对应的值
+/// 未提供值的占位符保持原样
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                match values.iter().find(|(key, _)| *key == name) {
+                    Some((_, value)) => output.push_str(&escape_html(value)),
+                    None => output.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                // 没有闭合的占位符，原样输出剩余内容
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// 转义 HTML 特殊字符，防止配置值破坏页面结构
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_placeholders() {
+        let html = render("<h1>{{title}}</h1><p>{{ event_name }}</p>", &[
+            ("title", "倒计时"),
+            ("event_name", "Teacon"),
+        ]);
+        assert_eq!(html, "<h1>倒计时</h1><p>Teacon</p>");
+    }
+
+    #[test]
+    fn test_render_keeps_unknown_and_unclosed() {
+        assert_eq!(render("{{unknown}} {{open", &[]), "{{unknown}} {{open");
+    }
+
+    #[test]
+    fn test_render_escapes_values() {
+        assert_eq!(
+            render("{{title}}", &[("title", "<script>")]),
+            "&lt;script&gt;"
+        );
+    }
+}
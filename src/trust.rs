@@ -0,0 +1,52 @@
+/// 已知机器人 User-Agent 的增加权重
+pub const BOT_WEIGHT: f64 = 0.0;
+
+/// 当天已经计数过的 IP 再次点击按钮时的增加权重
+pub const REPEAT_IP_WEIGHT: f64 = 0.5;
+
+/// 视为机器人的 User-Agent 片段（小写匹配）
+const BOT_MARKERS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "headless",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "go-http-client",
+];
+
+/// 判断 User-Agent 是否为已知机器人
+fn is_known_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+    BOT_MARKERS.iter().any(|marker| user_agent.contains(marker))
+}
+
+/// 按简单的信任启发式计算按钮增加的权重
+/// 已知机器人计为 0；当天已经计数过的 IP 计为 0.5；其余计为 1
+/// 没有 User-Agent 的请求不视为机器人，避免误伤不发送该头的客户端
+pub fn increment_weight(user_agent: Option<&str>, repeat_ip: bool) -> f64 {
+    if user_agent.is_some_and(is_known_bot) {
+        BOT_WEIGHT
+    } else if repeat_ip {
+        REPEAT_IP_WEIGHT
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increment_weight() {
+        let browser = Some("Mozilla/5.0 (Windows NT 10.0; Win64; x64) Firefox/128.0");
+        assert_eq!(increment_weight(browser, false), 1.0);
+        assert_eq!(increment_weight(browser, true), REPEAT_IP_WEIGHT);
+        assert_eq!(increment_weight(None, false), 1.0);
+        assert_eq!(increment_weight(Some("Googlebot/2.1"), false), BOT_WEIGHT);
+        // 机器人优先于重复 IP
+        assert_eq!(increment_weight(Some("curl/8.5.0"), true), BOT_WEIGHT);
+    }
+}